@@ -0,0 +1,82 @@
+use raytracer::animation::*;
+use raytracer::camera::*;
+use raytracer::color::*;
+use raytracer::primitives::*;
+use raytracer::render::*;
+use raytracer::sampling::*;
+use raytracer::scene::*;
+use raytracer::structs::*;
+
+// number of spheres in the scenes measuring how the work scales with the
+// size of the scene
+pub const OBJECT_COUNTS: [usize; 4] = [1, 10, 100, 1000];
+
+// 80 degrees, as in the renderer
+pub const FOV: Float = 1.396263;
+
+// camera at the origin looking along +z
+pub fn camera(width: usize, height: usize) -> Camera {
+    Camera::from_orientation(VEC3_ZERO, QUAT_IDENTITY, FOV, width, height)
+}
+
+// rays through the pixel centers of an image, in scanline order
+pub fn primary_rays(camera: &Camera, width: usize, height: usize) -> Vec<Ray> {
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| image_ray(camera, width, height, x as Float + 0.5, y as Float + 0.5))
+        .collect()
+}
+
+// randomly placed spheres in front of the camera above a checkerboard, the
+// spheres shrink with their number so they cover about the same part of
+// the image
+pub fn sphere_scene(count: usize) -> Scene {
+    let mut rng = Rng::new(count as u64);
+    let radius = 1.5 / (count as Float).cbrt();
+    let spheres = (0..count)
+        .map(|_| Sphere {
+            center: Vec3 {
+                x: (rng.next_float() - 0.5) * 8.,
+                y: (rng.next_float() - 0.5) * 4.,
+                z: 4. + rng.next_float() * 8.,
+            },
+            radius,
+            options: ShadingOptions {
+                base_color: Color {
+                    r: rng.next_float(),
+                    g: rng.next_float(),
+                    b: rng.next_float(),
+                    a: 1.,
+                },
+                reflective_part: 0.3,
+                ..WHITE_OPTIONS
+            },
+            motion: NO_MOTION,
+        })
+        .collect();
+
+    Scene {
+        lights: vec![LightSource {
+            pos: Vec3 {
+                x: 2.,
+                y: 6.,
+                z: 0.,
+            },
+            color: COLOR_WHITE,
+        }],
+        spheres,
+        boards: vec![Checkerboard {
+            axis: Axis::YAxis,
+            pos: Vec3 {
+                x: 0.,
+                y: -3.,
+                z: 8.,
+            },
+            radius: 10.,
+            grid_size: 1.,
+            options: WHITE_OPTIONS,
+            motion: NO_MOTION,
+        }],
+        fog: None,
+    }
+}
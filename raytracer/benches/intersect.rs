@@ -0,0 +1,78 @@
+// rays per second intersected with single primitives and with whole scenes
+// of increasing size, one by one and in packets
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use raytracer::packet::*;
+use raytracer::primitives::*;
+
+mod common;
+use common::*;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 64;
+
+fn primitives(c: &mut Criterion) {
+    let scene = sphere_scene(1);
+    let rays = primary_rays(&camera(WIDTH, HEIGHT), WIDTH, HEIGHT);
+
+    let mut group = c.benchmark_group("primitives");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    group.bench_function("Sphere::intersect", |b| {
+        let sphere = &scene.spheres[0];
+        b.iter(|| {
+            for ray in &rays {
+                black_box(sphere.intersect(black_box(ray)));
+            }
+        })
+    });
+    group.bench_function("Checkerboard::intersect", |b| {
+        let board = &scene.boards[0];
+        b.iter(|| {
+            for ray in &rays {
+                black_box(board.intersect(black_box(ray)));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn scenes(c: &mut Criterion) {
+    let rays = primary_rays(&camera(WIDTH, HEIGHT), WIDTH, HEIGHT);
+
+    let mut group = c.benchmark_group("Scene::intersect");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    for count in OBJECT_COUNTS {
+        let scene = sphere_scene(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &scene, |b, scene| {
+            b.iter(|| {
+                for ray in &rays {
+                    black_box(scene.intersect(black_box(ray)));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// the same rays as in scenes, intersected in packets
+fn packets(c: &mut Criterion) {
+    let rays = primary_rays(&camera(WIDTH, HEIGHT), WIDTH, HEIGHT);
+
+    let mut group = c.benchmark_group("Scene::intersect_packet");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    for count in OBJECT_COUNTS {
+        let scene = sphere_scene(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &scene, |b, scene| {
+            b.iter(|| {
+                for packet in rays.chunks(LANES) {
+                    black_box(scene.intersect_packet(black_box(packet)));
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, primitives, scenes, packets);
+criterion_main!(benches);
@@ -0,0 +1,49 @@
+// whole frames shaded with Scene::shade, pixels per second
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use raytracer::demo::*;
+use raytracer::scene::*;
+use raytracer::structs::*;
+
+mod common;
+use common::*;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 90;
+
+fn shade_frame(scene: &Scene, rays: &[Ray]) {
+    for ray in rays {
+        black_box(scene.shade(ray, DEFAULT_RENDER_SETTINGS.max_reflection_depth));
+    }
+}
+
+fn demo(c: &mut Criterion) {
+    let description = demo_scene();
+    let scene = description.at(0.);
+    let camera = description.camera(VEC3_ZERO, 0., 0., 0., FOV, WIDTH, HEIGHT, 0.);
+    let rays = primary_rays(&camera, WIDTH, HEIGHT);
+
+    let mut group = c.benchmark_group("frame");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    group.bench_function("demo", |b| b.iter(|| shade_frame(&scene, &rays)));
+    group.finish();
+}
+
+fn scenes(c: &mut Criterion) {
+    let rays = primary_rays(&camera(WIDTH, HEIGHT), WIDTH, HEIGHT);
+
+    let mut group = c.benchmark_group("frame/spheres");
+    group.throughput(Throughput::Elements(rays.len() as u64));
+    group.sample_size(10);
+    for count in OBJECT_COUNTS {
+        let scene = sphere_scene(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &scene, |b, scene| {
+            b.iter(|| shade_frame(scene, &rays))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, demo, scenes);
+criterion_main!(benches);
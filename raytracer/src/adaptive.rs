@@ -0,0 +1,302 @@
+use super::camera::*;
+use super::film::*;
+use super::render::*;
+use super::sampling::*;
+use super::scene::*;
+use super::structs::*;
+
+// ADAPTIVE SAMPLING
+// every pixel starts with min_samples samples, afterwards batches of samples
+// are only added to pixels whose estimated error is above the threshold. The
+// error is the standard error of the mean luminance, relative to the
+// luminance itself, so dark and bright pixels converge equally.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSettings {
+    pub min_samples: usize,
+    pub max_samples: usize,
+    // samples added to unconverged pixels per round
+    pub batch_size: usize,
+    // tolerated relative standard error
+    pub threshold: Float,
+}
+
+pub const DEFAULT_ADAPTIVE_SETTINGS: AdaptiveSettings = AdaptiveSettings {
+    min_samples: 8,
+    max_samples: 256,
+    batch_size: 8,
+    threshold: 0.02,
+};
+
+// luminance below which errors are measured in absolute terms, so pixels
+// close to black do not receive the maximum number of samples
+const MIN_LUMINANCE: Float = 0.05;
+
+// running mean and variance of the luminance of a pixel's samples (Welford)
+#[derive(Debug, Copy, Clone, Default)]
+struct PixelStats {
+    count: usize,
+    mean: Float,
+    m2: Float,
+}
+
+impl PixelStats {
+    fn add(&mut self, value: Float) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as Float;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn relative_error(&self) -> Float {
+        if self.count < 2 {
+            return Float::INFINITY;
+        }
+        let variance = self.m2 / (self.count - 1) as Float;
+        (variance / self.count as Float).sqrt() / self.mean.max(MIN_LUMINANCE)
+    }
+}
+
+// renders the film with adaptive sample counts, returns the number of
+// samples taken for every pixel of the film's region
+pub fn render_adaptive(
+    scene: &Scene,
+    camera: &Camera,
+    sampler: &dyn Sampler,
+    settings: &AdaptiveSettings,
+    render_settings: &RenderSettings,
+    film: &mut Film,
+) -> Vec<usize> {
+    let (start_x, end_x, start_y, end_y) = film.sample_bounds();
+    let bounds_width = end_x - start_x;
+    let mut stats = vec![PixelStats::default(); bounds_width * (end_y - start_y)];
+
+    let mut batch = settings.min_samples.max(1);
+    loop {
+        let mut active = false;
+        for y in start_y..end_y {
+            for x in start_x..end_x {
+                let pixel = &mut stats[(y - start_y) * bounds_width + x - start_x];
+                let converged = pixel.count >= settings.min_samples
+                    && pixel.relative_error() <= settings.threshold;
+                if pixel.count >= settings.max_samples || converged {
+                    continue;
+                }
+                active = true;
+
+                let first_sample = pixel.count;
+                let last_sample = (first_sample + batch).min(settings.max_samples);
+                for i in first_sample..last_sample {
+                    let (sample_x, sample_y, ray) =
+                        camera_sample(camera, sampler, film, x, y, i, render_settings);
+
+                    let sample = scene.shade_sample(&ray, render_settings);
+                    film.add_shading_sample(sample_x, sample_y, &sample, scene);
+                    pixel.add(sample.color.luminance());
+                }
+            }
+        }
+        if !active {
+            break;
+        }
+        batch = settings.batch_size.max(1);
+    }
+
+    let mut counts = Vec::with_capacity((film.end_x - film.start_x) * (film.end_y - film.start_y));
+    for y in film.start_y..film.end_y {
+        for x in film.start_x..film.end_x {
+            counts.push(stats[(y - start_y) * bounds_width + x - start_x].count);
+        }
+    }
+    counts
+}
+
+// 8 bit RGBA heat map of sample counts, from black for no samples to light
+// yellow for max_samples
+pub fn sample_heat_map(counts: &[usize], max_samples: usize) -> Vec<u8> {
+    // stops of the inferno color map
+    const STOPS: [[Float; 3]; 6] = [
+        [0., 0., 4.],
+        [66., 10., 104.],
+        [147., 38., 103.],
+        [221., 81., 58.],
+        [252., 165., 10.],
+        [252., 255., 164.],
+    ];
+
+    let mut rgba = Vec::with_capacity(4 * counts.len());
+    for &count in counts {
+        let x = (count as Float / max_samples.max(1) as Float).clamp(0., 1.);
+        let position = x * (STOPS.len() - 1) as Float;
+        let i = (position as usize).min(STOPS.len() - 2);
+        let t = position - i as Float;
+        for (a, b) in STOPS[i].iter().zip(&STOPS[i + 1]) {
+            rgba.push((a * (1. - t) + b * t + 0.5) as u8);
+        }
+        rgba.push(255);
+    }
+    rgba
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::*;
+    use crate::media::*;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 6;
+
+    fn camera() -> Camera {
+        Camera::create(
+            VEC3_ZERO,
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            1.4,
+            WIDTH,
+            HEIGHT,
+        )
+    }
+
+    // nothing but the background, every sample of a pixel has the same color
+    fn empty_scene() -> Scene {
+        Scene {
+            lights: vec![LIGHT1],
+            spheres: vec![],
+            boards: vec![],
+            fog: None,
+        }
+    }
+
+    // light scattered by fog, sampled stochastically along every ray
+    fn foggy_scene() -> Scene {
+        Scene {
+            fog: Some(Medium::fog(0.3, COLOR_WHITE, 0.)),
+            ..empty_scene()
+        }
+    }
+
+    fn render(scene: &Scene, settings: &AdaptiveSettings) -> Vec<usize> {
+        let mut film = Film::new(WIDTH, HEIGHT, 0, WIDTH, 0, HEIGHT, Filter::Box);
+        render_adaptive(
+            scene,
+            &camera(),
+            &SobolSampler,
+            settings,
+            &DEFAULT_RENDER_SETTINGS,
+            &mut film,
+        )
+    }
+
+    #[test]
+    fn pixel_stats() {
+        let mut stats = PixelStats::default();
+        assert_eq!(stats.relative_error(), Float::INFINITY);
+        stats.add(1.);
+        assert_eq!(stats.relative_error(), Float::INFINITY);
+        for value in [2., 3., 4.] {
+            stats.add(value);
+        }
+        // mean 2.5, sample variance 5 / 3
+        assert!((stats.mean - 2.5).abs() < 1e-6);
+        let expected = (5. / 3. / 4. as Float).sqrt() / 2.5;
+        assert!((stats.relative_error() - expected).abs() < 1e-6);
+
+        // errors of dark pixels are relative to MIN_LUMINANCE
+        let mut dark = PixelStats::default();
+        for value in [0., 0.01, 0., 0.01] {
+            dark.add(value);
+        }
+        let expected = (0.0001 / 3. / 4. as Float).sqrt() / MIN_LUMINANCE;
+        assert!((dark.relative_error() - expected).abs() < 1e-6);
+    }
+
+    // pixels without variance are done after min_samples, even if no error
+    // is tolerated
+    #[test]
+    fn constant_pixels_stop_at_min_samples() {
+        let settings = AdaptiveSettings {
+            threshold: 0.,
+            ..DEFAULT_ADAPTIVE_SETTINGS
+        };
+        let counts = render(&empty_scene(), &settings);
+        assert_eq!(counts.len(), WIDTH * HEIGHT);
+        assert!(counts.iter().all(|&count| count == settings.min_samples));
+    }
+
+    #[test]
+    fn noisy_pixels_are_sampled_until_converged() {
+        let strict = AdaptiveSettings {
+            min_samples: 4,
+            max_samples: 64,
+            batch_size: 4,
+            threshold: 0.,
+        };
+        let counts = render(&foggy_scene(), &strict);
+        assert!(counts.iter().all(|&count| count == strict.max_samples));
+
+        let tolerant = AdaptiveSettings {
+            threshold: Float::INFINITY,
+            ..strict
+        };
+        let counts = render(&foggy_scene(), &tolerant);
+        assert!(counts.iter().all(|&count| count == strict.min_samples));
+
+        // in between, lower thresholds take more samples
+        let total = |threshold: Float| -> usize {
+            render(
+                &foggy_scene(),
+                &AdaptiveSettings {
+                    threshold,
+                    ..strict
+                },
+            )
+            .iter()
+            .sum()
+        };
+        assert!(total(0.02) > total(0.1));
+    }
+
+    // batches that do not divide the sample counts are cut off at
+    // max_samples
+    #[test]
+    fn sample_counts_stay_within_bounds() {
+        let settings = AdaptiveSettings {
+            min_samples: 3,
+            max_samples: 17,
+            batch_size: 5,
+            threshold: 0.05,
+        };
+        for scene in [empty_scene(), foggy_scene()] {
+            let counts = render(&scene, &settings);
+            assert!(counts
+                .iter()
+                .all(|&count| (settings.min_samples..=settings.max_samples).contains(&count)));
+        }
+        let counts = render(
+            &foggy_scene(),
+            &AdaptiveSettings {
+                threshold: 0.,
+                ..settings
+            },
+        );
+        assert!(counts.iter().all(|&count| count == 17));
+    }
+
+    #[test]
+    fn heat_map_spans_the_color_map() {
+        let rgba = sample_heat_map(&[0, 32, 64, 100], 64);
+        assert_eq!(rgba.len(), 16);
+        assert_eq!(rgba[0..4], [0, 0, 4, 255]);
+        assert_eq!(rgba[8..12], [252, 255, 164, 255]);
+        // counts past max_samples are clamped
+        assert_eq!(rgba[12..16], rgba[8..12]);
+    }
+}
@@ -0,0 +1,553 @@
+use std::ops::Deref;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::color::*;
+use super::media::*;
+use super::packet::*;
+use super::primitives::*;
+use super::structs::*;
+
+// KEYFRAME ANIMATION
+// any property of a scene description is either a constant or a list of
+// keyframes, in scene files:
+//
+//   "radius": 0.3
+//   "radius": { "keyframes": [
+//     { "time": 0, "value": 0.3 },
+//     { "time": 2, "value": 0.5, "interpolation": "Step" }
+//   ], "repeat": true }
+//
+// the interpolation of a keyframe applies to the segment leading up to it.
+// Bezier segments are cubic Bezier curves in value space, from the previous
+// keyframe's value over its out_control and this keyframe's in_control to this
+// keyframe's value. Controls default to the values themselves, which eases in
+// and out of keyframes.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum Interpolation {
+    Step,
+    #[default]
+    Linear,
+    Bezier,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe<T> {
+    pub time: Float,
+    pub value: T,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_control: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub out_control: Option<T>,
+}
+
+// a list of keyframes that is never empty, so animated values always have a
+// first and a last keyframe
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Keyframes<T>(Vec<Keyframe<T>>);
+
+impl<T> Keyframes<T> {
+    // None for an empty list
+    pub fn new(keyframes: Vec<Keyframe<T>>) -> Option<Keyframes<T>> {
+        if keyframes.is_empty() {
+            None
+        } else {
+            Some(Keyframes(keyframes))
+        }
+    }
+
+    pub fn first(&self) -> &Keyframe<T> {
+        &self.0[0]
+    }
+
+    pub fn last(&self) -> &Keyframe<T> {
+        &self.0[self.0.len() - 1]
+    }
+}
+
+impl<T> Deref for Keyframes<T> {
+    type Target = [Keyframe<T>];
+
+    fn deref(&self) -> &[Keyframe<T>] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Animated<T> {
+    Keyframes {
+        // sorted by time, values are held before the first and after the last
+        keyframes: Keyframes<T>,
+        // loop the keyframes instead of holding the last value
+        repeat: bool,
+    },
+    Constant(T),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframesFields<T> {
+    keyframes: Vec<Keyframe<T>>,
+    #[serde(default)]
+    repeat: bool,
+}
+
+// objects with a keyframes field are keyframes, anything else is a constant.
+// Unlike an untagged enum, this reports why a value is invalid, and invalid
+// keyframes do not pass as a constant whose fields all have defaults.
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Animated<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Animated<T>, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if value.get("keyframes").is_some() {
+            let fields = KeyframesFields::deserialize(value).map_err(D::Error::custom)?;
+            Ok(Animated::Keyframes {
+                keyframes: Keyframes::new(fields.keyframes)
+                    .ok_or_else(|| D::Error::custom("keyframes must not be empty"))?,
+                repeat: fields.repeat,
+            })
+        } else {
+            T::deserialize(value)
+                .map(Animated::Constant)
+                .map_err(D::Error::custom)
+        }
+    }
+}
+
+impl<T: Default> Default for Animated<T> {
+    fn default() -> Animated<T> {
+        Animated::Constant(T::default())
+    }
+}
+
+// values that can be interpolated, s is in [0, 1]
+pub trait Animatable: Clone {
+    fn lerp(&self, other: &Self, s: Float) -> Self;
+}
+
+impl<T: Animatable> Animated<T> {
+    pub fn at(&self, t: Float) -> T {
+        let (keyframes, repeat) = match self {
+            Animated::Constant(value) => return value.clone(),
+            Animated::Keyframes { keyframes, repeat } => (keyframes, *repeat),
+        };
+        let (first, last) = (keyframes.first(), keyframes.last());
+        let duration = last.time - first.time;
+        let t = if repeat && duration > 0. {
+            first.time + (t - first.time).rem_euclid(duration)
+        } else {
+            t
+        };
+
+        // index of the first keyframe after t
+        let next = keyframes.partition_point(|keyframe| keyframe.time <= t);
+        if next == 0 {
+            return first.value.clone();
+        }
+        if next == keyframes.len() {
+            return last.value.clone();
+        }
+        let (from, to) = (&keyframes[next - 1], &keyframes[next]);
+        let s = (t - from.time) / (to.time - from.time);
+
+        match to.interpolation {
+            Interpolation::Step => from.value.clone(),
+            Interpolation::Linear => from.value.lerp(&to.value, s),
+            Interpolation::Bezier => {
+                let p1 = from.out_control.as_ref().unwrap_or(&from.value);
+                let p2 = to.in_control.as_ref().unwrap_or(&to.value);
+                // de Casteljau's algorithm
+                let a = from.value.lerp(p1, s);
+                let b = p1.lerp(p2, s);
+                let c = p2.lerp(&to.value, s);
+                let ab = a.lerp(&b, s);
+                let bc = b.lerp(&c, s);
+                ab.lerp(&bc, s)
+            }
+        }
+    }
+
+    // all values the property takes on at keyframes
+    pub fn values(&self) -> Vec<&T> {
+        match self {
+            Animated::Constant(value) => vec![value],
+            Animated::Keyframes { keyframes, .. } => {
+                keyframes.iter().map(|keyframe| &keyframe.value).collect()
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Animated::Keyframes { keyframes, .. } = self {
+            if keyframes
+                .windows(2)
+                .any(|pair| pair[0].time >= pair[1].time)
+            {
+                return Err("keyframe times must be increasing".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+// movement of an object while the shutter is open. Rays at time dt relative
+// to the scene see the object at its animated position at time + dt, so
+// motion blur follows the actual path, including curves, steps and the jump
+// back of repeated keyframes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Motion {
+    // animated position of the object
+    pub path: Animated<Vec3>,
+    // time of the scene on the path
+    pub time: Float,
+}
+
+pub const NO_MOTION: Motion = Motion {
+    path: Animated::Constant(VEC3_ZERO),
+    time: 0.,
+};
+
+impl Default for Motion {
+    fn default() -> Motion {
+        NO_MOTION
+    }
+}
+
+impl Motion {
+    // offset from the object's position in the scene at time dt
+    pub fn offset(&self, dt: Float) -> Vec3 {
+        match self.path {
+            Animated::Constant(_) => VEC3_ZERO,
+            Animated::Keyframes { .. } => self.path.at(self.time + dt) - self.path.at(self.time),
+        }
+    }
+
+    // offsets at the times of all lanes, each computed like offset
+    pub fn offset_packet(&self, dt: FloatV) -> Vec3V {
+        if let Animated::Constant(_) = self.path {
+            return Vec3V::splat(VEC3_ZERO);
+        }
+        let offsets = dt.to_array().map(|dt| self.offset(dt));
+        Vec3V {
+            x: FloatV::new(offsets.map(|offset| offset.x)),
+            y: FloatV::new(offsets.map(|offset| offset.y)),
+            z: FloatV::new(offsets.map(|offset| offset.z)),
+        }
+    }
+}
+
+impl Animatable for Float {
+    fn lerp(&self, other: &Float, s: Float) -> Float {
+        self + (other - self) * s
+    }
+}
+
+impl Animatable for Vec3 {
+    fn lerp(&self, other: &Vec3, s: Float) -> Vec3 {
+        Vec3::lerp(*self, *other, s)
+    }
+}
+
+// rotations are interpolated along great arcs
+impl Animatable for Quat {
+    fn lerp(&self, other: &Quat, s: Float) -> Quat {
+        self.slerp(*other, s)
+    }
+}
+
+impl Animatable for Color {
+    fn lerp(&self, other: &Color, s: Float) -> Color {
+        Color::lerp(*self, *other, s)
+    }
+}
+
+impl Animatable for PhaseFunction {
+    fn lerp(&self, other: &PhaseFunction, s: Float) -> PhaseFunction {
+        match (*self, *other) {
+            (PhaseFunction::HenyeyGreenstein(a), PhaseFunction::HenyeyGreenstein(b)) => {
+                PhaseFunction::HenyeyGreenstein(a.lerp(&b, s))
+            }
+            // isotropic scattering is Henyey-Greenstein with g = 0
+            (PhaseFunction::Isotropic, PhaseFunction::HenyeyGreenstein(b)) => {
+                PhaseFunction::HenyeyGreenstein(b * s)
+            }
+            (PhaseFunction::HenyeyGreenstein(a), PhaseFunction::Isotropic) => {
+                PhaseFunction::HenyeyGreenstein(a * (1. - s))
+            }
+            (PhaseFunction::Isotropic, PhaseFunction::Isotropic) => PhaseFunction::Isotropic,
+        }
+    }
+}
+
+impl Animatable for Medium {
+    fn lerp(&self, other: &Medium, s: Float) -> Medium {
+        Medium {
+            sigma_a: self.sigma_a.lerp(other.sigma_a, s),
+            sigma_s: self.sigma_s.lerp(other.sigma_s, s),
+            phase: self.phase.lerp(&other.phase, s),
+        }
+    }
+}
+
+// media fade in and out of existence like a medium that does not interact
+// with light at all
+impl Animatable for Option<Medium> {
+    fn lerp(&self, other: &Option<Medium>, s: Float) -> Option<Medium> {
+        let vacuum = Medium {
+            sigma_a: COLOR_ZERO,
+            sigma_s: COLOR_ZERO,
+            phase: PhaseFunction::Isotropic,
+        };
+        match (self, other) {
+            (None, None) => None,
+            (Some(a), Some(b)) => Some(a.lerp(b, s)),
+            (Some(a), None) => Some(a.lerp(&vacuum, s)),
+            (None, Some(b)) => Some(vacuum.lerp(b, s)),
+        }
+    }
+}
+
+impl Animatable for ShadingOptions {
+    fn lerp(&self, other: &ShadingOptions, s: Float) -> ShadingOptions {
+        ShadingOptions {
+            base_color: self.base_color.lerp(other.base_color, s),
+            ambiant_part: self.ambiant_part.lerp(&other.ambiant_part, s),
+            diffuse_part: self.diffuse_part.lerp(&other.diffuse_part, s),
+            specular_part: self.specular_part.lerp(&other.specular_part, s),
+            specular_coefficient: self
+                .specular_coefficient
+                .lerp(&other.specular_coefficient, s),
+            reflective_part: self.reflective_part.lerp(&other.reflective_part, s),
+            refraction_index: self.refraction_index.lerp(&other.refraction_index, s),
+            interior: self.interior.lerp(&other.interior, s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: Float, value: Float, interpolation: Interpolation) -> Keyframe<Float> {
+        Keyframe {
+            time,
+            value,
+            interpolation,
+            in_control: None,
+            out_control: None,
+        }
+    }
+
+    fn keyframes(keyframes: Vec<Keyframe<Float>>, repeat: bool) -> Animated<Float> {
+        Animated::Keyframes {
+            keyframes: Keyframes::new(keyframes).unwrap(),
+            repeat,
+        }
+    }
+
+    fn assert_close(a: Float, b: Float) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn constants_are_constant() {
+        let radius = Animated::Constant(0.5);
+        assert_eq!(radius.at(-3.), 0.5);
+        assert_eq!(radius.at(100.), 0.5);
+        assert_eq!(radius.values(), vec![&0.5]);
+    }
+
+    #[test]
+    fn linear_keyframes() {
+        let animated = keyframes(
+            vec![
+                keyframe(1., 2., Interpolation::Linear),
+                keyframe(3., 6., Interpolation::Linear),
+                keyframe(4., 0., Interpolation::Linear),
+            ],
+            false,
+        );
+        // values are held outside of the keyframes
+        assert_eq!(animated.at(0.), 2.);
+        assert_eq!(animated.at(1.), 2.);
+        assert_close(animated.at(2.), 4.);
+        assert_eq!(animated.at(3.), 6.);
+        assert_close(animated.at(3.25), 4.5);
+        assert_eq!(animated.at(4.), 0.);
+        assert_eq!(animated.at(10.), 0.);
+    }
+
+    // the value jumps at the time of a step keyframe
+    #[test]
+    fn step_keyframes() {
+        let animated = keyframes(
+            vec![
+                keyframe(0., 1., Interpolation::Linear),
+                keyframe(1., 5., Interpolation::Step),
+            ],
+            false,
+        );
+        assert_eq!(animated.at(0.5), 1.);
+        assert_eq!(animated.at(0.999), 1.);
+        assert_eq!(animated.at(1.), 5.);
+        assert_eq!(animated.at(1.5), 5.);
+    }
+
+    #[test]
+    fn bezier_keyframes() {
+        // without controls, segments ease in and out
+        let eased = keyframes(
+            vec![
+                keyframe(0., 0., Interpolation::Linear),
+                keyframe(1., 1., Interpolation::Bezier),
+            ],
+            false,
+        );
+        assert_close(eased.at(0.5), 0.5);
+        assert_close(eased.at(0.25), 0.15625);
+        assert_close(eased.at(0.75), 0.84375);
+
+        // controls at the end value leave the start at full speed
+        let mut controlled = vec![
+            keyframe(0., 0., Interpolation::Linear),
+            keyframe(2., 1., Interpolation::Bezier),
+        ];
+        controlled[0].out_control = Some(1.);
+        controlled[1].in_control = Some(1.);
+        let animated = keyframes(controlled, false);
+        for s in [0., 0.2, 0.5, 0.9, 1.] {
+            assert_close(animated.at(2. * s), 1. - (1. - s).powi(3));
+        }
+    }
+
+    #[test]
+    fn repeated_keyframes() {
+        let animated = keyframes(
+            vec![
+                keyframe(1., 0., Interpolation::Linear),
+                keyframe(3., 4., Interpolation::Linear),
+            ],
+            true,
+        );
+        assert_close(animated.at(2.), 2.);
+        assert_close(animated.at(4.), 2.);
+        assert_close(animated.at(102.5), 3.);
+        // also before the first keyframe
+        assert_close(animated.at(0.), 2.);
+        assert_close(animated.at(-1.5), 3.);
+
+        // a single keyframe has no duration to repeat
+        let single = keyframes(vec![keyframe(1., 7., Interpolation::Linear)], true);
+        assert_eq!(single.at(5.), 7.);
+    }
+
+    #[test]
+    fn keyframes_are_never_empty() {
+        assert_eq!(Keyframes::<Float>::new(vec![]), None);
+        let keyframes = Keyframes::new(vec![
+            keyframe(0., 1., Interpolation::Linear),
+            keyframe(1., 2., Interpolation::Linear),
+        ])
+        .unwrap();
+        assert_eq!(keyframes.first().value, 1.);
+        assert_eq!(keyframes.last().value, 2.);
+        assert_eq!(keyframes.len(), 2);
+    }
+
+    #[test]
+    fn validation() {
+        assert!(Animated::Constant(1.).validate().is_ok());
+        assert!(
+            keyframes(vec![keyframe(0., 1., Interpolation::Step)], false)
+                .validate()
+                .is_ok()
+        );
+        for times in [[1., 1.], [2., 1.]] {
+            let animated = keyframes(
+                times
+                    .iter()
+                    .map(|&time| keyframe(time, 0., Interpolation::Linear))
+                    .collect(),
+                false,
+            );
+            assert_eq!(
+                animated.validate(),
+                Err("keyframe times must be increasing".to_string())
+            );
+        }
+    }
+
+    fn parse<'de, T: Deserialize<'de>>(json: &'de str) -> Result<Animated<T>, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(parse::<Float>("0.5"), Ok(Animated::Constant(0.5)));
+        assert_eq!(
+            parse::<Float>(
+                r#"{ "keyframes": [
+                    { "time": 0, "value": 1 },
+                    { "time": 1, "value": 2, "interpolation": "Step" }
+                ], "repeat": true }"#
+            ),
+            Ok(keyframes(
+                vec![
+                    keyframe(0., 1., Interpolation::Linear),
+                    keyframe(1., 2., Interpolation::Step),
+                ],
+                true
+            ))
+        );
+        let options = parse::<ShadingOptions>(r#"{ "diffuse_part": 0.25 }"#).unwrap();
+        assert_eq!(options.at(0.).diffuse_part, 0.25);
+
+        let animated = keyframes(vec![keyframe(0., 1., Interpolation::Bezier)], false);
+        let json = serde_json::to_string(&animated).unwrap();
+        assert_eq!(parse::<Float>(&json), Ok(animated));
+    }
+
+    // errors name the actual problem
+    #[test]
+    fn parsing_errors() {
+        let error = parse::<Float>(r#"{ "keyframes": [{ "time": 0, "value": "one" }] }"#);
+        assert!(error.unwrap_err().contains("invalid type: string \"one\""));
+
+        let error = parse::<Float>(
+            r#"{ "keyframes": [{ "time": 0, "value": 1, "interpolation": "Cubic" }] }"#,
+        );
+        assert!(error.unwrap_err().contains("unknown variant `Cubic`"));
+
+        let error = parse::<Float>(r#"{ "keyframes": [{ "time": 0, "valeu": 1 }] }"#);
+        assert!(error.unwrap_err().contains("unknown field `valeu`"));
+
+        let error = parse::<Float>(r#"{ "keyframes": [] }"#);
+        assert!(error.unwrap_err().contains("keyframes must not be empty"));
+
+        let error = parse::<Float>(r#"{ "keyframes": [], "repeats": true }"#);
+        assert!(error.unwrap_err().contains("unknown field `repeats`"));
+
+        let error = parse::<Float>(r#""large""#);
+        assert!(error
+            .unwrap_err()
+            .contains("invalid type: string \"large\""));
+    }
+
+    // all fields of shading options have defaults, broken keyframes must not
+    // pass as default options
+    #[test]
+    fn invalid_keyframes_are_no_constants() {
+        let error = parse::<ShadingOptions>(
+            r#"{ "keyframes": [{ "time": 0, "value": { "diffuse_part": "high" } }] }"#,
+        );
+        assert!(error.unwrap_err().contains("invalid type: string \"high\""));
+
+        let error = parse::<ShadingOptions>(r#"{ "keyframes": { "time": 0 } }"#);
+        assert!(error.unwrap_err().contains("expected a sequence"));
+    }
+}
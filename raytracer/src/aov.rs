@@ -0,0 +1,503 @@
+use super::color::*;
+use super::output::*;
+use super::scene::*;
+use super::structs::*;
+use super::tonemap::*;
+
+// ARBITRARY OUTPUT VARIABLES
+// per-pixel buffers describing the first hit of the camera rays, rendered
+// alongside the beauty image for compositing and denoising. Samples are
+// averaged over the pixel they fall into (box filtered), ids are taken from
+// the first sample of a pixel.
+#[derive(Clone)]
+pub struct AovBuffers {
+    pub width: usize,
+    pub height: usize,
+    samples: Vec<Float>,
+    hits: Vec<Float>,
+    depth: Vec<Float>,
+    normal: Vec<Vec3>,
+    albedo: Vec<Color>,
+    uv: Vec<(Float, Float)>,
+    direct: Vec<Color>,
+    indirect: Vec<Color>,
+    object_id: Vec<Option<usize>>,
+    material_id: Vec<Option<usize>>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize) -> AovBuffers {
+        let size = width * height;
+        AovBuffers {
+            width,
+            height,
+            samples: vec![0.; size],
+            hits: vec![0.; size],
+            depth: vec![0.; size],
+            normal: vec![VEC3_ZERO; size],
+            albedo: vec![COLOR_ZERO; size],
+            uv: vec![(0., 0.); size],
+            direct: vec![COLOR_ZERO; size],
+            indirect: vec![COLOR_ZERO; size],
+            object_id: vec![None; size],
+            material_id: vec![None; size],
+        }
+    }
+
+    // adds a sample to the pixel with the given index
+    pub fn add(&mut self, i: usize, sample: &ShadingSample, scene: &Scene) {
+        if self.samples[i] == 0. {
+            self.object_id[i] = sample.hit.map(|hit| hit.object_id);
+            self.material_id[i] = sample.hit.map(|hit| scene.material_id(hit.object_id));
+        }
+        self.samples[i] += 1.;
+        self.direct[i] += sample.direct;
+        self.indirect[i] += sample.indirect;
+
+        if let Some(hit) = sample.hit {
+            let (u, v) = scene.uv(hit.object_id, &hit.point, hit.time);
+            self.hits[i] += 1.;
+            self.depth[i] += hit.distance;
+            self.normal[i] += hit.normal;
+            self.albedo[i] += hit.options.base_color;
+            self.uv[i] = (self.uv[i].0 + u, self.uv[i].1 + v);
+        }
+    }
+
+    // distance to the first hit, infinite if no sample hit anything
+    pub fn depth(&self, i: usize) -> Float {
+        if self.hits[i] == 0. {
+            return Float::INFINITY;
+        }
+        self.depth[i] / self.hits[i]
+    }
+
+    pub fn normal(&self, i: usize) -> Vec3 {
+        if self.hits[i] == 0. {
+            return VEC3_ZERO;
+        }
+        self.normal[i].normalize()
+    }
+
+    // base color of the first hit, black for the background
+    pub fn albedo(&self, i: usize) -> Color {
+        self.average(&self.albedo, i)
+    }
+
+    pub fn uv(&self, i: usize) -> (Float, Float) {
+        if self.hits[i] == 0. {
+            return (0., 0.);
+        }
+        (self.uv[i].0 / self.hits[i], self.uv[i].1 / self.hits[i])
+    }
+
+    pub fn direct(&self, i: usize) -> Color {
+        self.average(&self.direct, i)
+    }
+
+    pub fn indirect(&self, i: usize) -> Color {
+        self.average(&self.indirect, i)
+    }
+
+    pub fn object_id(&self, i: usize) -> Option<usize> {
+        self.object_id[i]
+    }
+
+    pub fn material_id(&self, i: usize) -> Option<usize> {
+        self.material_id[i]
+    }
+
+    // copies all buffers of other, whose top left pixel is at (x, y) in
+    // these buffers
+    pub fn insert(&mut self, other: &AovBuffers, x: usize, y: usize) {
+        assert!(x + other.width <= self.width && y + other.height <= self.height);
+        let width = self.width;
+        let rect = (x, y, other.width, other.height);
+        copy_rect(&mut self.samples, width, &other.samples, rect);
+        copy_rect(&mut self.hits, width, &other.hits, rect);
+        copy_rect(&mut self.depth, width, &other.depth, rect);
+        copy_rect(&mut self.normal, width, &other.normal, rect);
+        copy_rect(&mut self.albedo, width, &other.albedo, rect);
+        copy_rect(&mut self.uv, width, &other.uv, rect);
+        copy_rect(&mut self.direct, width, &other.direct, rect);
+        copy_rect(&mut self.indirect, width, &other.indirect, rect);
+        copy_rect(&mut self.object_id, width, &other.object_id, rect);
+        copy_rect(&mut self.material_id, width, &other.material_id, rect);
+    }
+
+    fn average(&self, buffer: &[Color], i: usize) -> Color {
+        if self.samples[i] == 0. {
+            return COLOR_ZERO;
+        }
+        buffer[i].scale(1. / self.samples[i])
+    }
+
+    // OpenEXR layers, ids are stored as floats with -1 for the background
+    pub fn channels(&self) -> Vec<ExrChannel> {
+        let size = self.width * self.height;
+        let channel = |name: &str, value: &dyn Fn(usize) -> Float| ExrChannel {
+            name: name.to_string(),
+            values: (0..size).map(value).collect(),
+        };
+        let id = |id: Option<usize>| id.map_or(-1., |id| id as Float);
+
+        let mut channels = vec![
+            channel("depth.Z", &|i| self.depth(i)),
+            channel("normal.X", &|i| self.normal(i).x),
+            channel("normal.Y", &|i| self.normal(i).y),
+            channel("normal.Z", &|i| self.normal(i).z),
+            channel("uv.U", &|i| self.uv(i).0),
+            channel("uv.V", &|i| self.uv(i).1),
+            channel("id.object", &|i| id(self.object_id(i))),
+            channel("id.material", &|i| id(self.material_id(i))),
+        ];
+        for (layer, buffer) in [
+            ("albedo", &self.albedo),
+            ("direct", &self.direct),
+            ("indirect", &self.indirect),
+        ] {
+            let pixels: Vec<Color> = (0..size).map(|i| self.average(buffer, i)).collect();
+            channels.extend(color_channels(layer, &pixels).into_iter().take(3));
+        }
+        channels
+    }
+
+    // 8 bit RGBA visualizations of all buffers, named like their EXR layers
+    pub fn images(&self, tone_mapping: &ToneMapping) -> Vec<(&'static str, Vec<u8>)> {
+        let size = self.width * self.height;
+        let max_depth = (0..size)
+            .map(|i| self.depth(i))
+            .filter(|d| d.is_finite())
+            .fold(0., Float::max);
+
+        let image = |pixel: &dyn Fn(usize) -> [u8; 3]| {
+            let mut rgba = Vec::with_capacity(4 * size);
+            for i in 0..size {
+                rgba.extend_from_slice(&pixel(i));
+                rgba.push(255);
+            }
+            rgba
+        };
+        let unit = |x: Float| (x.clamp(0., 1.) * 255. + 0.5) as u8;
+        let id_color = |id: Option<usize>| match id {
+            // golden ratio hues, so neighbouring ids get distinct colors
+            Some(id) => {
+                let hue = (id as Float * 0.618034).fract();
+                let channel = |offset: Float| unit(0.5 + 0.5 * (2. * PI * (hue + offset)).cos());
+                [channel(0.), channel(1. / 3.), channel(2. / 3.)]
+            }
+            None => [0, 0, 0],
+        };
+
+        vec![
+            (
+                "depth",
+                image(&|i| {
+                    let d = self.depth(i);
+                    let v = if d.is_finite() {
+                        unit(1. - d / max_depth)
+                    } else {
+                        0
+                    };
+                    [v, v, v]
+                }),
+            ),
+            (
+                "normal",
+                image(&|i| {
+                    let n = self.normal(i);
+                    if self.hits[i] == 0. {
+                        return [0, 0, 0];
+                    }
+                    [
+                        unit(0.5 + 0.5 * n.x),
+                        unit(0.5 + 0.5 * n.y),
+                        unit(0.5 + 0.5 * n.z),
+                    ]
+                }),
+            ),
+            (
+                "albedo",
+                image(&|i| {
+                    let c = self.albedo(i);
+                    [encode_srgb8(c.r), encode_srgb8(c.g), encode_srgb8(c.b)]
+                }),
+            ),
+            (
+                "uv",
+                image(&|i| {
+                    let (u, v) = self.uv(i);
+                    [unit(u), unit(v), 0]
+                }),
+            ),
+            ("object", image(&|i| id_color(self.object_id(i)))),
+            ("material", image(&|i| id_color(self.material_id(i)))),
+            ("direct", image(&|i| tone_mapping.to_srgb8(self.direct(i)))),
+            (
+                "indirect",
+                image(&|i| tone_mapping.to_srgb8(self.indirect(i))),
+            ),
+        ]
+    }
+}
+
+// copies the rows of a buffer of size (width, height) into a buffer with rows
+// of to_width pixels, at (x, y)
+fn copy_rect<T: Copy>(
+    to: &mut [T],
+    to_width: usize,
+    from: &[T],
+    (x, y, width, height): (usize, usize, usize, usize),
+) {
+    for row in 0..height {
+        let start = (y + row) * to_width + x;
+        to[start..start + width].copy_from_slice(&from[row * width..(row + 1) * width]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::*;
+    use crate::camera::*;
+    use crate::film::*;
+    use crate::primitives::*;
+    use crate::render::*;
+    use crate::sampling::*;
+
+    const WIDTH: usize = 4;
+    const HEIGHT: usize = 1;
+
+    // one sample through the center of every pixel
+    struct PixelCenters;
+
+    impl Sampler for PixelCenters {
+        fn sample_2d(&self, _: usize, _: usize, _: usize, _: usize) -> (Float, Float) {
+            (0.5, 0.5)
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera::create(
+            VEC3_ZERO,
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            1.4,
+            WIDTH,
+            HEIGHT,
+        )
+    }
+
+    // unit direction of the ray through the center of pixel x
+    fn direction(x: usize) -> Vec3 {
+        image_ray(&camera(), WIDTH, HEIGHT, x as Float + 0.5, 0.5)
+            .direction
+            .normalize()
+    }
+
+    // spheres of radius 1 at distance 10 in the first three pixels, the first
+    // and the last of them made of the same material, the fourth pixel shows
+    // the background
+    fn scene() -> Scene {
+        let sphere = |x: usize, options: ShadingOptions| Sphere {
+            center: direction(x).scale(10.),
+            radius: 1.,
+            options,
+            motion: NO_MOTION,
+        };
+        Scene {
+            lights: vec![LIGHT1],
+            spheres: vec![
+                sphere(0, WHITE_OPTIONS),
+                sphere(1, OPTIONS),
+                sphere(2, WHITE_OPTIONS),
+            ],
+            boards: vec![],
+            fog: None,
+        }
+    }
+
+    fn render(scene: &Scene) -> (Film, AovBuffers) {
+        let mut film = Film::new(WIDTH, HEIGHT, 0, WIDTH, 0, HEIGHT, Filter::Box);
+        film.enable_aovs();
+        render_film(
+            scene,
+            &camera(),
+            &PixelCenters,
+            1,
+            &DEFAULT_RENDER_SETTINGS,
+            &mut film,
+        );
+        let aovs = film.aovs.clone().unwrap();
+        (film, aovs)
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).norm() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn first_hits() {
+        let scene = scene();
+        let (_, aovs) = render(&scene);
+        for (x, sphere) in scene.spheres.iter().enumerate() {
+            let point = sphere.center - direction(x);
+            assert!((aovs.depth(x) - 9.).abs() < 1e-4);
+            assert_close(aovs.normal(x), -direction(x));
+            assert_eq!(aovs.albedo(x), sphere.options.base_color);
+            let (u, v) = aovs.uv(x);
+            let expected = scene.uv(x, &point, 0.);
+            assert!((u - expected.0).abs() < 1e-4 && (v - expected.1).abs() < 1e-4);
+            assert_eq!(aovs.object_id(x), Some(x));
+        }
+        let material_ids: Vec<_> = (0..WIDTH).map(|x| aovs.material_id(x)).collect();
+        assert_eq!(material_ids, [Some(0), Some(1), Some(0), None]);
+    }
+
+    #[test]
+    fn misses() {
+        let (_, aovs) = render(&scene());
+        assert_eq!(aovs.depth(3), Float::INFINITY);
+        assert_eq!(aovs.normal(3), VEC3_ZERO);
+        assert_eq!(aovs.uv(3), (0., 0.));
+        assert_eq!(aovs.albedo(3), COLOR_ZERO);
+        assert_eq!(aovs.object_id(3), None);
+        // the background is black
+        assert_eq!(aovs.direct(3), COLOR_ZERO);
+        assert_eq!(aovs.indirect(3), COLOR_ZERO);
+    }
+
+    // the lighting split of the beauty image
+    #[test]
+    fn direct_and_indirect_light() {
+        let (film, aovs) = render(&scene());
+        for x in 0..WIDTH {
+            let sum = aovs.direct(x) + aovs.indirect(x);
+            let pixel = film.pixel(x, 0);
+            assert!((sum.r - pixel.r).abs() < 1e-5 && (sum.b - pixel.b).abs() < 1e-5);
+        }
+        assert!(aovs.direct(1).b > 0.);
+    }
+
+    // hit features are averaged over the samples that hit something, colors
+    // over all samples, ids come from the first sample
+    #[test]
+    fn samples_are_averaged() {
+        let scene = scene();
+        let hit = |object_id: usize, distance: Float, normal: Vec3| SurfaceHit {
+            object_id,
+            t: distance,
+            distance,
+            time: 0.,
+            point: scene.spheres[object_id].center - direction(object_id),
+            normal,
+            options: scene.spheres[object_id].options,
+        };
+        let sample = |hit: Option<SurfaceHit>, direct: Color| ShadingSample {
+            color: direct,
+            direct,
+            indirect: COLOR_WHITE,
+            hit,
+        };
+        let x = Vec3 {
+            x: 1.,
+            y: 0.,
+            z: 0.,
+        };
+        let y = Vec3 {
+            x: 0.,
+            y: 1.,
+            z: 0.,
+        };
+
+        let mut aovs = AovBuffers::new(1, 1);
+        aovs.add(0, &sample(Some(hit(1, 2., x)), COLOR_WHITE), &scene);
+        aovs.add(0, &sample(None, COLOR_ZERO), &scene);
+        aovs.add(0, &sample(Some(hit(0, 4., y)), COLOR_WHITE), &scene);
+        assert_eq!(aovs.depth(0), 3.);
+        assert_close(aovs.normal(0), (x + y).normalize());
+        let albedo = aovs.albedo(0);
+        let expected = (OPTIONS.base_color + WHITE_OPTIONS.base_color).scale(1. / 3.);
+        assert!((albedo.r - expected.r).abs() < 1e-6 && (albedo.b - expected.b).abs() < 1e-6);
+        assert!((aovs.direct(0).g - 2. / 3.).abs() < 1e-6);
+        assert_eq!(aovs.indirect(0).g, 1.);
+        assert_eq!(aovs.object_id(0), Some(1));
+        assert_eq!(aovs.material_id(0), Some(1));
+    }
+
+    #[test]
+    fn insert_at_offset() {
+        let scene = scene();
+        let (_, part) = render(&scene);
+        let mut aovs = AovBuffers::new(6, 3);
+        aovs.insert(&part, 2, 1);
+        for y in 0..3 {
+            for x in 0..6 {
+                let i = y * 6 + x;
+                if y == 1 && x >= 2 {
+                    assert_eq!(aovs.depth(i), part.depth(x - 2));
+                    assert_eq!(aovs.normal(i), part.normal(x - 2));
+                    assert_eq!(aovs.uv(i), part.uv(x - 2));
+                    assert_eq!(aovs.direct(i), part.direct(x - 2));
+                    assert_eq!(aovs.object_id(i), part.object_id(x - 2));
+                    assert_eq!(aovs.material_id(i), part.material_id(x - 2));
+                } else {
+                    assert_eq!(aovs.depth(i), Float::INFINITY);
+                    assert_eq!(aovs.object_id(i), None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_out_of_bounds() {
+        AovBuffers::new(4, 4).insert(&AovBuffers::new(2, 2), 3, 0);
+    }
+
+    #[test]
+    fn exr_channels() {
+        let (_, aovs) = render(&scene());
+        let channels = aovs.channels();
+        let names: Vec<&str> = channels.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "depth.Z",
+                "normal.X",
+                "normal.Y",
+                "normal.Z",
+                "uv.U",
+                "uv.V",
+                "id.object",
+                "id.material",
+                "albedo.R",
+                "albedo.G",
+                "albedo.B",
+                "direct.R",
+                "direct.G",
+                "direct.B",
+                "indirect.R",
+                "indirect.G",
+                "indirect.B",
+            ]
+        );
+        for channel in &channels {
+            assert_eq!(channel.values.len(), WIDTH * HEIGHT);
+        }
+        let values = |name: &str| &channels.iter().find(|c| c.name == name).unwrap().values;
+        assert_eq!(values("id.object"), &[0., 1., 2., -1.]);
+        assert_eq!(values("id.material"), &[0., 1., 0., -1.]);
+        assert_eq!(values("depth.Z")[3], Float::INFINITY);
+        assert_eq!(values("normal.Z")[1], aovs.normal(1).z);
+        assert_eq!(values("albedo.B")[1], OPTIONS.base_color.b);
+    }
+}
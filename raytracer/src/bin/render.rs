@@ -0,0 +1,487 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process::{self, Child, Command, Stdio};
+
+use raytracer::adaptive::*;
+use raytracer::demo::*;
+use raytracer::denoise::*;
+use raytracer::description::*;
+use raytracer::film::*;
+use raytracer::output::*;
+use raytracer::progressive::*;
+use raytracer::sampling::*;
+use raytracer::scene::*;
+use raytracer::structs::*;
+use raytracer::tonemap::*;
+
+const USAGE: &str = "usage: render [options] <output.png|output.exr|output.hdr>
+
+with --frames, the first run of # in the output file name is replaced by the
+frame number, padded with zeros (e.g. frames/####.png), the number is appended
+to the file name if there is none. Frames that already exist are skipped, so interrupted
+renders resume where they stopped.
+
+options:
+  --width <pixels>        image width (default 960)
+  --height <pixels>       image height (default 540)
+  --samples <count>       samples per pixel (default 16)
+  --passes <count>        render the samples this many times, refining the image
+                          and rewriting the output after every pass (default 1)
+  --adaptive <error>      keep adding samples to pixels until their relative error is
+                          below this threshold, --samples is then the maximum count
+  --sample-map            also output a heat map of the samples taken per pixel as
+                          <output>.samples.png, requires --adaptive
+  --reflections <count>   maximum number of reflections along a ray (default 4)
+  --refractions <count>   maximum number of refractions along a ray (default 8)
+  --min-contribution <x>  skip rays contributing less than this to a pixel (default 0.001)
+  --throughput-cutoff <x> randomly terminate rays contributing less than this (default 0.01)
+  --scene <file.json>     render a scene file instead of the rotating spheres demo
+  --time <seconds>        animation time of the scene (default 0)
+  --frames <first>-<last> render this range of animation frames, frame n shows the
+                          scene at time --time + n / --fps
+  --fps <rate>            frames per second of the animation (default 24)
+  --shutter <seconds>     blur moving objects over this interval around each image's
+                          time, e.g. 0.5 / fps for a 180 degree shutter (default 0)
+  --ffmpeg <video>        also encode the frames into this video file, by piping
+                          them into ffmpeg, requires --frames
+  --fov <degrees>         field of view (default 80)
+  --filter <name>         box, tent, gaussian, mitchell or lanczos (default gaussian)
+  --tone-mapper <name>    clamp, reinhard, aces or agx, for PNG output (default aces)
+  --exposure <stops>      exposure adjustment, for PNG output (default 0)
+  --half                  store half instead of full floats in EXR output
+  --denoise               denoise the image, guided by the AOV buffers
+  --aovs                  also output depth, normal, albedo, uv, id and direct and
+                          indirect lighting buffers, as layers of EXR output or as
+                          separate PNG images next to other outputs";
+
+struct Options {
+    width: usize,
+    height: usize,
+    samples: usize,
+    passes: usize,
+    adaptive: Option<Float>,
+    sample_map: bool,
+    render_settings: RenderSettings,
+    scene: Option<String>,
+    time: Float,
+    frames: Option<(usize, usize)>,
+    fps: Float,
+    video: Option<String>,
+    fov: Float,
+    filter: Filter,
+    tone_mapping: ToneMapping,
+    pixel_type: PixelType,
+    aovs: bool,
+    denoise: bool,
+    output: String,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        width: 960,
+        height: 540,
+        samples: 16,
+        passes: 1,
+        adaptive: None,
+        sample_map: false,
+        render_settings: DEFAULT_RENDER_SETTINGS,
+        scene: None,
+        time: 0.,
+        frames: None,
+        fps: 24.,
+        video: None,
+        fov: 80.,
+        filter: Filter::Gaussian,
+        tone_mapping: DEFAULT_TONE_MAPPING,
+        pixel_type: PixelType::Float,
+        aovs: false,
+        denoise: false,
+        output: String::new(),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--width" => options.width = parse(&value()?)?,
+            "--height" => options.height = parse(&value()?)?,
+            "--samples" => options.samples = parse(&value()?)?,
+            "--passes" => options.passes = parse(&value()?)?,
+            "--adaptive" => options.adaptive = Some(parse(&value()?)?),
+            "--sample-map" => options.sample_map = true,
+            "--reflections" => options.render_settings.max_reflection_depth = parse(&value()?)?,
+            "--refractions" => options.render_settings.max_refraction_depth = parse(&value()?)?,
+            "--min-contribution" => options.render_settings.min_contribution = parse(&value()?)?,
+            "--throughput-cutoff" => options.render_settings.throughput_cutoff = parse(&value()?)?,
+            "--scene" => options.scene = Some(value()?),
+            "--time" => options.time = parse(&value()?)?,
+            "--frames" => {
+                let range = value()?;
+                let (first, last) = range
+                    .split_once('-')
+                    .ok_or(format!("invalid frame range {}", range))?;
+                options.frames = Some((parse(first)?, parse(last)?));
+            }
+            "--fps" => options.fps = parse(&value()?)?,
+            "--shutter" => options.render_settings.shutter = parse(&value()?)?,
+            "--ffmpeg" => options.video = Some(value()?),
+            "--fov" => options.fov = parse(&value()?)?,
+            "--filter" => {
+                options.filter = match value()?.as_str() {
+                    "box" => Filter::Box,
+                    "tent" => Filter::Tent,
+                    "gaussian" => Filter::Gaussian,
+                    "mitchell" => Filter::MitchellNetravali,
+                    "lanczos" => Filter::Lanczos,
+                    name => return Err(format!("unknown filter {}", name)),
+                }
+            }
+            "--tone-mapper" => {
+                options.tone_mapping.tone_mapper = match value()?.as_str() {
+                    "clamp" => ToneMapper::Clamp,
+                    "reinhard" => ToneMapper::Reinhard,
+                    "aces" => ToneMapper::AcesFilmic,
+                    "agx" => ToneMapper::AgX,
+                    name => return Err(format!("unknown tone mapper {}", name)),
+                }
+            }
+            "--exposure" => options.tone_mapping.exposure = parse(&value()?)?,
+            "--half" => options.pixel_type = PixelType::Half,
+            "--aovs" => options.aovs = true,
+            "--denoise" => options.denoise = true,
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => options.output = arg,
+        }
+    }
+
+    if options.output.is_empty() {
+        return Err("missing output file".to_string());
+    }
+    if options.adaptive.is_some() && options.passes > 1 {
+        return Err("--adaptive cannot be combined with --passes".to_string());
+    }
+    if options.sample_map && options.adaptive.is_none() {
+        return Err("--sample-map requires --adaptive".to_string());
+    }
+    if options.video.is_some() && options.frames.is_none() {
+        return Err("--ffmpeg requires --frames".to_string());
+    }
+    if matches!(options.frames, Some((first, last)) if first > last) {
+        return Err("the first frame comes after the last".to_string());
+    }
+    if options.render_settings.shutter < 0. {
+        return Err("--shutter must not be negative".to_string());
+    }
+    if options.fps <= 0. {
+        return Err("--fps must be positive".to_string());
+    }
+    Ok(options)
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {}", value))
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|message| {
+        if !message.is_empty() {
+            eprintln!("error: {}\n", message);
+        }
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    });
+
+    let description = match &options.scene {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path, e))
+            .and_then(|json| SceneDescription::from_json(&json))
+            .unwrap_or_else(|message| {
+                eprintln!("error: {}", message);
+                process::exit(1);
+            }),
+        None => demo_scene(),
+    };
+
+    match options.frames {
+        Some((first, last)) => render_frames(&options, &description, first, last),
+        None => {
+            render_image(&options, &description, options.time, &options.output, true);
+        }
+    }
+}
+
+// renders the frames first to last, skipping frames that were rendered before
+fn render_frames(options: &Options, description: &SceneDescription, first: usize, last: usize) {
+    let mut ffmpeg = options.video.as_ref().map(|video| {
+        let ffmpeg = spawn_ffmpeg(options, video);
+        exit_on_error(ffmpeg)
+    });
+
+    for frame in first..=last {
+        let path = frame_path(&options.output, frame);
+        let time = options.time + frame as Float / options.fps;
+        let done = Path::new(&path).exists();
+        if done && ffmpeg.is_none() {
+            eprintln!("frame {}: {} exists, skipping", frame, path);
+            continue;
+        }
+
+        // frames rendered before are read back for the video if possible
+        let previous = if done {
+            File::open(&path)
+                .and_then(|file| read_png(BufReader::new(file)))
+                .ok()
+                .filter(|&(width, height, _)| (width, height) == (options.width, options.height))
+        } else {
+            None
+        };
+        let rgba = match previous {
+            Some((_, _, rgba)) => {
+                eprintln!("frame {}: {} exists, skipping", frame, path);
+                rgba
+            }
+            None => {
+                let film = render_image(options, description, time, &path, false);
+                eprintln!("frame {}: {}", frame, path);
+                film.develop(&options.tone_mapping)
+            }
+        };
+
+        if let Some(ffmpeg) = &mut ffmpeg {
+            let stdin = ffmpeg.stdin.as_mut().unwrap();
+            exit_on_error(stdin.write_all(&rgba).map_err(|e| {
+                std::io::Error::new(e.kind(), format!("cannot write to ffmpeg: {}", e))
+            }));
+        }
+    }
+
+    if let Some(mut ffmpeg) = ffmpeg {
+        drop(ffmpeg.stdin.take());
+        let status = exit_on_error(ffmpeg.wait());
+        if !status.success() {
+            eprintln!("error: ffmpeg failed with {}", status);
+            process::exit(1);
+        }
+    }
+}
+
+// ffmpeg reading raw RGBA frames from its standard input
+fn spawn_ffmpeg(options: &Options, video: &str) -> std::io::Result<Child> {
+    Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error"])
+        .args(["-f", "rawvideo", "-pixel_format", "rgba"])
+        .args([
+            "-video_size",
+            &format!("{}x{}", options.width, options.height),
+        ])
+        .args(["-framerate", &options.fps.to_string()])
+        .args(["-i", "-", "-pix_fmt", "yuv420p", video])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| std::io::Error::new(e.kind(), format!("cannot run ffmpeg: {}", e)))
+}
+
+// output path of a frame, see USAGE
+fn frame_path(output: &str, frame: usize) -> String {
+    let (directory, name) = match output.rsplit_once('/') {
+        Some((directory, name)) => (format!("{}/", directory), name),
+        None => (String::new(), output),
+    };
+    match name.find('#') {
+        Some(start) => {
+            let digits = name[start..].chars().take_while(|&c| c == '#').count();
+            format!(
+                "{}{}{:0width$}{}",
+                directory,
+                &name[..start],
+                frame,
+                &name[start + digits..],
+                width = digits
+            )
+        }
+        None => {
+            let stem = output_stem(name);
+            format!("{}{}.{:04}{}", directory, stem, frame, &name[stem.len()..])
+        }
+    }
+}
+
+// renders the scene at the given time to the output path, rewriting it after
+// every pass if write_passes is set, returns the final film
+fn render_image(
+    options: &Options,
+    description: &SceneDescription,
+    time: Float,
+    output: &str,
+    write_passes: bool,
+) -> Film {
+    let (width, height) = (options.width, options.height);
+    let scene = description.at(time);
+    let camera = description.camera(
+        VEC3_ZERO,
+        0.,
+        0.,
+        0.,
+        options.fov.to_radians(),
+        width,
+        height,
+        time,
+    );
+    let sampler = SobolSampler;
+    let mut film = Film::new(width, height, 0, width, 0, height, options.filter);
+    if options.aovs || options.denoise {
+        film.enable_aovs();
+    }
+
+    if let Some(threshold) = options.adaptive {
+        let settings = AdaptiveSettings {
+            max_samples: options.samples,
+            min_samples: DEFAULT_ADAPTIVE_SETTINGS.min_samples.min(options.samples),
+            threshold,
+            ..DEFAULT_ADAPTIVE_SETTINGS
+        };
+        let counts = render_adaptive(
+            &scene,
+            &camera,
+            &sampler,
+            &settings,
+            &options.render_settings,
+            &mut film,
+        );
+        let total: usize = counts.iter().sum();
+        eprintln!(
+            "{:.1} samples per pixel on average",
+            total as Float / counts.len() as Float
+        );
+        if options.sample_map {
+            let path = format!("{}.samples.png", output_stem(output));
+            let heat_map = sample_heat_map(&counts, options.samples);
+            exit_on_error(create(&path).and_then(|w| write_png(w, width, height, &heat_map)));
+        }
+        let film = finish(options, film);
+        exit_on_error(write_output(options, output, &film));
+        return film;
+    }
+
+    let mut renderer = ProgressiveRenderer::new(film, options.samples);
+    for pass in 1..=options.passes {
+        renderer.render_pass(&scene, &camera, &sampler, &options.render_settings);
+        if write_passes && pass < options.passes {
+            let film = finish(options, renderer.film.clone());
+            exit_on_error(write_output(options, output, &film));
+        }
+        if options.passes > 1 {
+            eprintln!(
+                "pass {}/{}: {} samples per pixel",
+                pass,
+                options.passes,
+                pass * options.samples
+            );
+        }
+    }
+    let film = finish(options, renderer.film);
+    exit_on_error(write_output(options, output, &film));
+    film
+}
+
+fn exit_on_error<T>(result: std::io::Result<T>) -> T {
+    result.unwrap_or_else(|message| {
+        eprintln!("error: {}", message);
+        process::exit(1);
+    })
+}
+
+// output path without its extension
+fn output_stem(output: &str) -> &str {
+    match output.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => output,
+    }
+}
+
+// denoises the film if requested and drops AOVs that are not output
+fn finish(options: &Options, mut film: Film) -> Film {
+    if options.denoise {
+        denoise(&mut film, &DEFAULT_DENOISE_SETTINGS);
+    }
+    if !options.aovs {
+        film.aovs = None;
+    }
+    film
+}
+
+// writes the image and its AOVs, the image itself is written last and
+// replaced atomically, so it only exists once all outputs are complete
+fn write_output(options: &Options, output: &str, film: &Film) -> std::io::Result<()> {
+    let (width, height) = (options.width, options.height);
+    let extension = output.rsplit('.').next().unwrap_or_default();
+    if let (Some(aovs), false) = (&film.aovs, extension == "exr") {
+        let stem = output_stem(output);
+        for (name, rgba) in aovs.images(&options.tone_mapping) {
+            let path = format!("{}.{}.png", stem, name);
+            create(&path).and_then(|w| write_png(w, width, height, &rgba))?;
+        }
+    }
+
+    let temporary = format!("{}.tmp", output);
+    create(&temporary).and_then(|mut writer| match extension {
+        "exr" => {
+            let mut channels = color_channels("", &film.resolve());
+            if let Some(aovs) = &film.aovs {
+                channels.extend(aovs.channels());
+            }
+            write_exr(&mut writer, width, height, &channels, options.pixel_type)?;
+            writer.flush()
+        }
+        "hdr" => {
+            write_hdr(&mut writer, width, height, &film.resolve())?;
+            writer.flush()
+        }
+        _ => write_png(writer, width, height, &film.develop(&options.tone_mapping)),
+    })?;
+    std::fs::rename(&temporary, output)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("cannot write {}: {}", output, e)))
+}
+
+fn create(path: &str) -> std::io::Result<BufWriter<File>> {
+    let file = File::create(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("cannot create {}: {}", path, e)))?;
+    Ok(BufWriter::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_numbers_replace_hashes() {
+        assert_eq!(frame_path("frames/####.png", 7), "frames/0007.png");
+        assert_eq!(frame_path("shot_##_v2.exr", 3), "shot_03_v2.exr");
+        assert_eq!(frame_path("#.hdr", 42), "42.hdr");
+        // numbers longer than the padding are not cut off
+        assert_eq!(frame_path("##.png", 1234), "1234.png");
+        // only the first run is replaced
+        assert_eq!(frame_path("a#b#.png", 5), "a5b#.png");
+    }
+
+    #[test]
+    fn frame_numbers_are_appended_without_hashes() {
+        assert_eq!(frame_path("out.png", 12), "out.0012.png");
+        assert_eq!(frame_path("renders/out.exr", 0), "renders/out.0000.exr");
+        assert_eq!(frame_path("out", 3), "out.0003");
+        assert_eq!(frame_path("out.final.png", 3), "out.final.0003.png");
+    }
+
+    // hashes and dots in directories are not part of the file name
+    #[test]
+    fn directories_are_kept() {
+        assert_eq!(frame_path("take#1/out.png", 2), "take#1/out.0002.png");
+        assert_eq!(frame_path("v1.2/out", 2), "v1.2/out.0002");
+        assert_eq!(frame_path("v1.2/##.png", 2), "v1.2/02.png");
+        assert_eq!(frame_path("/tmp/a/b/f###", 9), "/tmp/a/b/f009");
+    }
+}
@@ -2,6 +2,7 @@ use super::structs::*;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Camera {
     pub origin: Vec3,
     pub x_direction: Vec3,
@@ -40,10 +41,95 @@ impl Camera {
         }
     }
 
+    // camera at origin looking along orientation applied to +z, with +y
+    // pointing up
+    pub fn from_orientation(
+        origin: Vec3,
+        orientation: Quat,
+        fov: Float,
+        width: usize,
+        height: usize,
+    ) -> Camera {
+        let axis = |x, y, z| orientation.rotate(Vec3 { x, y, z });
+        Camera::create(
+            origin,
+            axis(1., 0., 0.),
+            axis(0., 1., 0.),
+            fov,
+            width,
+            height,
+        )
+    }
+
+    // maps camera space, where the camera sits at the origin and looks along
+    // +z with the screen at z = 1, to the scene
+    pub fn to_world(&self) -> Mat4 {
+        Mat4::affine(
+            Mat3::from_columns(self.x_direction, self.y_direction, self.direction),
+            self.origin,
+        )
+    }
+
     pub fn grid_ray(&self, x: Float, y: Float) -> Ray {
+        let to_world = self.to_world();
         Ray {
-            origin: self.origin,
-            direction: self.direction + self.x_direction.scale(x) + self.y_direction.scale(y),
+            origin: to_world.transform_point(ORIGIN).into(),
+            direction: to_world.transform_vector(Vec3 { x, y, z: 1. }),
+            time: 0.,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).norm() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn grid_rays() {
+        let origin = Vec3 {
+            x: 1.,
+            y: 2.,
+            z: 3.,
+        };
+        let camera =
+            Camera::from_orientation(origin, Quat::from_euler(0.3, -0.5, 0.2), PI / 2., 100, 100);
+        let ray = camera.grid_ray(0., 0.);
+        assert_eq!(ray.origin, origin);
+        assert_close(ray.direction, camera.direction);
+        // a 90 degree field of view spans one unit to each side of the screen
+        let corner = camera.grid_ray(1., -1.);
+        assert_close(
+            corner.direction,
+            camera.direction + camera.x_direction - camera.y_direction,
+        );
+        assert_close(
+            camera.x_direction,
+            camera.y_direction.cross(camera.direction),
+        );
+    }
+
+    // orientations rotate the camera's view of +z
+    #[test]
+    fn orientation() {
+        let orientation = Quat::from_axis_angle(
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            PI / 2.,
+        );
+        let camera = Camera::from_orientation(VEC3_ZERO, orientation, PI / 2., 100, 50);
+        let z = Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        };
+        assert_close(camera.grid_ray(0., 0.).direction, orientation.rotate(z));
+        assert_close(camera.to_world().linear() * z, orientation.to_mat3() * z);
+    }
+}
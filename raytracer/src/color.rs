@@ -1,10 +1,11 @@
+use serde::{Deserialize, Serialize};
 use std::ops;
 use wasm_bindgen::prelude::*;
 
 use super::structs::*;
 
 #[wasm_bindgen]
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Color {
     pub r: Float,
     pub g: Float,
@@ -21,6 +22,26 @@ impl Color {
             a: s * self.a,
         }
     }
+
+    // applies f to every channel
+    pub fn map(self, f: impl Fn(Float) -> Float) -> Color {
+        Color {
+            r: f(self.r),
+            g: f(self.g),
+            b: f(self.b),
+            a: f(self.a),
+        }
+    }
+
+    // self for s = 0, other for s = 1
+    pub fn lerp(self, other: Color, s: Float) -> Color {
+        self + (other - self) * s
+    }
+
+    // relative luminance of linear Rec. 709 primaries, ignores alpha
+    pub fn luminance(self) -> Float {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 }
 
 impl ops::Add<Color> for Color {
@@ -51,6 +72,58 @@ impl ops::Sub<Color> for Color {
     }
 }
 
+impl ops::SubAssign<Color> for Color {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+// component-wise product, e.g. of a surface's albedo and the light reaching it
+impl ops::Mul<Color> for Color {
+    type Output = Color;
+    fn mul(self, other: Color) -> Color {
+        Color {
+            r: self.r * other.r,
+            g: self.g * other.g,
+            b: self.b * other.b,
+            a: self.a * other.a,
+        }
+    }
+}
+impl ops::MulAssign<Color> for Color {
+    fn mul_assign(&mut self, other: Self) {
+        *self = *self * other;
+    }
+}
+impl ops::Mul<Float> for Color {
+    type Output = Color;
+    fn mul(self, s: Float) -> Color {
+        self.scale(s)
+    }
+}
+impl ops::Mul<Color> for Float {
+    type Output = Color;
+    fn mul(self, c: Color) -> Color {
+        c.scale(self)
+    }
+}
+impl ops::MulAssign<Float> for Color {
+    fn mul_assign(&mut self, s: Float) {
+        *self = *self * s;
+    }
+}
+impl ops::Div<Float> for Color {
+    type Output = Color;
+    fn div(self, s: Float) -> Color {
+        self.map(|c| c / s)
+    }
+}
+impl ops::DivAssign<Float> for Color {
+    fn div_assign(&mut self, s: Float) {
+        *self = *self / s;
+    }
+}
+
 pub const COLOR_ZERO: Color = Color {
     r: 0.,
     g: 0.,
@@ -93,3 +166,65 @@ pub const COLOR_BLUE: Color = Color {
     b: 1.,
     a: 1.,
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(r: Float, g: Float, b: Float, a: Float) -> Color {
+        Color { r, g, b, a }
+    }
+
+    #[test]
+    fn component_wise_product() {
+        let albedo = color(0.5, 0.25, 1., 1.);
+        let radiance = color(2., 4., 0.5, 1.);
+        assert_eq!(albedo * radiance, color(1., 1., 0.5, 1.));
+        let mut c = albedo;
+        c *= radiance;
+        assert_eq!(c, albedo * radiance);
+        assert_eq!(COLOR_WHITE * radiance, radiance);
+    }
+
+    #[test]
+    fn scalar_operators() {
+        let c = color(1., 2., 3., 1.);
+        assert_eq!(c * 2., color(2., 4., 6., 2.));
+        assert_eq!(2. * c, c * 2.);
+        assert_eq!(c * 2., c.scale(2.));
+        assert_eq!(c / 2., color(0.5, 1., 1.5, 0.5));
+        let mut d = c;
+        d *= 4.;
+        d /= 2.;
+        assert_eq!(d, c * 2.);
+    }
+
+    #[test]
+    fn sum_and_difference() {
+        let mut c = COLOR_RED;
+        c += COLOR_GREEN;
+        assert_eq!(c, color(1., 1., 0., 2.));
+        c -= COLOR_GREEN;
+        assert_eq!(c, COLOR_RED);
+        assert_eq!(COLOR_WHITE - COLOR_BLACK, color(1., 1., 1., 0.));
+    }
+
+    #[test]
+    fn map_and_lerp() {
+        let c = color(0.5, 2., -1., 1.);
+        assert_eq!(c.map(|x| x.min(1.)), color(0.5, 1., -1., 1.));
+        assert_eq!(
+            COLOR_BLACK.lerp(COLOR_WHITE, 0.25),
+            color(0.25, 0.25, 0.25, 1.)
+        );
+    }
+
+    #[test]
+    fn luminance() {
+        assert!((COLOR_WHITE.luminance() - 1.).abs() < 1e-6);
+        assert_eq!(COLOR_BLACK.luminance(), 0.);
+        assert!(COLOR_GREEN.luminance() > COLOR_RED.luminance());
+        assert!(COLOR_RED.luminance() > COLOR_BLUE.luminance());
+        assert_eq!(color(1., 1., 1., 0.).luminance(), COLOR_WHITE.luminance());
+    }
+}
@@ -0,0 +1,154 @@
+use super::animation::*;
+use super::color::*;
+use super::description::*;
+use super::primitives::*;
+use super::scene::*;
+use super::structs::*;
+
+// ROTATING_SHPERES
+// three small mirrors orbiting a blue sphere while the light swings around
+// them, the scene repeats every 8 PI seconds
+pub fn demo_scene() -> SceneDescription {
+    let reflective_options = ShadingOptions {
+        base_color: COLOR_GREEN,
+        ambiant_part: 0.2,
+        diffuse_part: 0.4,
+        specular_part: 0.,
+        specular_coefficient: 4.,
+        reflective_part: 0.4,
+        refraction_index: 0.,
+        interior: None,
+    };
+    let big_sphere = SphereDescription {
+        center: Animated::Constant(Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 5.,
+        }),
+        radius: Animated::Constant(1.),
+        options: Animated::Constant(ShadingOptions {
+            base_color: COLOR_BLUE,
+            ..OPTIONS
+        }),
+    };
+    let orbiting_sphere = |phase: Float| SphereDescription {
+        center: orbit(phase),
+        radius: Animated::Constant(0.3),
+        options: Animated::Constant(reflective_options),
+    };
+    let board = |z: Float| BoardDescription {
+        axis: Axis::ZAxis,
+        pos: Animated::Constant(Vec3 { x: 0., y: 0., z }),
+        radius: Animated::Constant(2.),
+        grid_size: Animated::Constant(0.25),
+        options: Animated::Constant(WHITE_OPTIONS),
+    };
+
+    SceneDescription {
+        camera: CameraDescription {
+            position: Animated::Constant(Vec3 {
+                x: 0.,
+                y: 2.5,
+                z: 3.,
+            }),
+            rotation: Animated::Constant(Vec3 {
+                x: (0.9 as Float).to_degrees(),
+                y: 0.,
+                z: 0.,
+            }),
+        },
+        lights: vec![LightDescription {
+            pos: light_path(),
+            color: Animated::Constant(Color {
+                r: 1.,
+                g: 1.,
+                b: 1.,
+                a: 1.,
+            }),
+        }],
+        spheres: vec![
+            big_sphere,
+            orbiting_sphere(0.),
+            orbiting_sphere(2. * PI / 3.),
+            orbiting_sphere(4. * PI / 3.),
+        ],
+        boards: vec![board(5.), board(0.)],
+        fog: Animated::Constant(None),
+    }
+}
+
+// circle of radius 1.15 at z = 4.1, one revolution every 4 PI seconds,
+// made of quarter circles
+fn orbit(phase: Float) -> Animated<Vec3> {
+    const RADIUS: Float = 1.15;
+    // control point distance of a Bezier quarter circle
+    const KAPPA: Float = 0.5523;
+    let keyframes = (0..=4)
+        .map(|i| {
+            let angle = phase + i as Float * PI / 2.;
+            let value = Vec3 {
+                x: RADIUS * angle.cos(),
+                y: RADIUS * angle.sin(),
+                z: 4.1,
+            };
+            let tangent = Vec3 {
+                x: -angle.sin(),
+                y: angle.cos(),
+                z: 0.,
+            }
+            .scale(KAPPA * RADIUS);
+            Keyframe {
+                time: i as Float * PI,
+                value,
+                interpolation: Interpolation::Bezier,
+                in_control: Some(value - tangent),
+                out_control: Some(value + tangent),
+            }
+        })
+        .collect();
+    Animated::Keyframes {
+        keyframes: Keyframes::new(keyframes).unwrap(),
+        repeat: true,
+    }
+}
+
+// the light swings along (5 cos u, 4 sin u, 1.8 u) with u = sin(t / 4 + 1.5),
+// sampled at 16 keyframes with control points along its velocity
+fn light_path() -> Animated<Vec3> {
+    const KEYFRAMES: usize = 16;
+    let period = 8. * PI;
+    // time between keyframes
+    let step = period / KEYFRAMES as Float;
+
+    let keyframes = (0..=KEYFRAMES)
+        .map(|i| {
+            let time = i as Float * step;
+            let u = (time / 4. + 1.5).sin();
+            let du = (time / 4. + 1.5).cos() / 4.;
+            let value = Vec3 {
+                x: 5. * u.cos(),
+                y: 4. * u.sin(),
+                z: 1.8 * u,
+            };
+            let velocity = Vec3 {
+                x: -5. * u.sin(),
+                y: 4. * u.cos(),
+                z: 1.8,
+            }
+            .scale(du);
+            // control points of a cubic Bezier curve with the given velocity
+            let control = velocity.scale(step / 3.);
+            Keyframe {
+                time,
+                value,
+                interpolation: Interpolation::Bezier,
+                in_control: Some(value - control),
+                out_control: Some(value + control),
+            }
+        })
+        .collect();
+    Animated::Keyframes {
+        keyframes: Keyframes::new(keyframes).unwrap(),
+        repeat: true,
+    }
+}
@@ -0,0 +1,355 @@
+use super::aov::*;
+use super::color::*;
+use super::film::*;
+use super::structs::*;
+
+// DENOISING
+// edge-avoiding à-trous wavelet filter (Dammertz et al. 2010). The beauty
+// image is divided by the albedo, smoothed with increasingly sparse 5x5
+// B-spline kernels that stop at differences in color, normal, depth and
+// albedo, and multiplied by the albedo again, so textures stay sharp.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DenoiseSettings {
+    // number of filter passes, each doubles the kernel footprint
+    pub iterations: usize,
+    // tolerated luminance difference, halved with every pass
+    pub sigma_color: Float,
+    // exponent applied to the cosine between normals
+    pub sigma_normal: Float,
+    // tolerated relative depth difference per pixel of distance
+    pub sigma_depth: Float,
+    pub sigma_albedo: Float,
+}
+
+pub const DEFAULT_DENOISE_SETTINGS: DenoiseSettings = DenoiseSettings {
+    iterations: 5,
+    sigma_color: 0.6,
+    sigma_normal: 64.,
+    sigma_depth: 0.02,
+    sigma_albedo: 0.1,
+};
+
+impl DenoiseSettings {
+    // distance in pixels over which the passes spread a pixel, so a region
+    // denoised with this many more pixels on every side matches the denoised
+    // full image
+    pub fn footprint(&self) -> usize {
+        2 * ((1 << self.iterations) - 1)
+    }
+}
+
+const KERNEL: [Float; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+// albedo below which a channel is filtered directly instead of demodulated
+const MIN_ALBEDO: Float = 0.01;
+
+// denoises the film in place, the filter is only guided by the image itself
+// unless AOVs were recorded
+pub fn denoise(film: &mut Film, settings: &DenoiseSettings) {
+    let (width, height) = (film.end_x - film.start_x, film.end_y - film.start_y);
+    let size = width * height;
+    let aovs = film.aovs.as_ref();
+
+    let albedo: Vec<Color> = (0..size)
+        .map(|i| aovs.map_or(COLOR_WHITE, |aovs| aovs.albedo(i)))
+        .collect();
+    let modulation = |c: Float| if c < MIN_ALBEDO { 1. } else { c };
+    let demodulate = |color: Color, albedo: Color| Color {
+        r: color.r / modulation(albedo.r),
+        g: color.g / modulation(albedo.g),
+        b: color.b / modulation(albedo.b),
+        a: color.a,
+    };
+
+    let mut image: Vec<Color> = film
+        .resolve()
+        .iter()
+        .zip(&albedo)
+        .map(|(&c, &a)| demodulate(c, a))
+        .collect();
+    let mut filtered = image.clone();
+
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        let sigma_color = settings.sigma_color / (1 << iteration) as Float;
+
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                let center = image[i];
+                let mut sum = COLOR_ZERO;
+                let mut weight_sum = 0.;
+
+                for (ky, ky_weight) in KERNEL.iter().enumerate() {
+                    let qy = y as isize + (ky as isize - 2) * step;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (kx, kx_weight) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (kx as isize - 2) * step;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+                        let j = qy as usize * width + qx as usize;
+
+                        let color_distance = (image[j].luminance() - center.luminance()).abs();
+                        let mut weight =
+                            ky_weight * kx_weight * (-color_distance / sigma_color.max(1e-5)).exp();
+                        if let Some(aovs) = aovs {
+                            weight *= feature_weight(aovs, settings, i, j, step);
+                        }
+
+                        sum += image[j].scale(weight);
+                        weight_sum += weight;
+                    }
+                }
+
+                filtered[i] = if weight_sum > 0. {
+                    sum.scale(1. / weight_sum)
+                } else {
+                    center
+                };
+            }
+        }
+
+        std::mem::swap(&mut image, &mut filtered);
+    }
+
+    let pixels = image
+        .iter()
+        .zip(&albedo)
+        .map(|(&c, &a)| Color {
+            r: c.r * modulation(a.r),
+            g: c.g * modulation(a.g),
+            b: c.b * modulation(a.b),
+            a: c.a,
+        })
+        .collect();
+    film.set_resolved(pixels);
+}
+
+// edge-stopping weight between pixels i and j from the AOV features
+fn feature_weight(
+    aovs: &AovBuffers,
+    settings: &DenoiseSettings,
+    i: usize,
+    j: usize,
+    step: isize,
+) -> Float {
+    let (depth_i, depth_j) = (aovs.depth(i), aovs.depth(j));
+    if depth_i.is_infinite() || depth_j.is_infinite() {
+        // never blur between the background and geometry
+        return if depth_i.is_infinite() && depth_j.is_infinite() {
+            1.
+        } else {
+            0.
+        };
+    }
+
+    let normal_weight = aovs
+        .normal(i)
+        .dot(aovs.normal(j))
+        .max(0.)
+        .powf(settings.sigma_normal);
+
+    let depth_tolerance = settings.sigma_depth * depth_i * step as Float + 1e-5;
+    let depth_weight = (-(depth_i - depth_j).abs() / depth_tolerance).exp();
+
+    let albedo_difference = aovs.albedo(i) - aovs.albedo(j);
+    let albedo_distance = albedo_difference.r * albedo_difference.r
+        + albedo_difference.g * albedo_difference.g
+        + albedo_difference.b * albedo_difference.b;
+    let albedo_weight = (-albedo_distance / (settings.sigma_albedo * settings.sigma_albedo)).exp();
+
+    normal_weight * depth_weight * albedo_weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::*;
+    use crate::scene::*;
+
+    const SIZE: usize = 32;
+
+    fn grey(v: Float) -> Color {
+        Color {
+            r: v,
+            g: v,
+            b: v,
+            a: 1.,
+        }
+    }
+
+    fn scene() -> Scene {
+        Scene {
+            lights: vec![],
+            spheres: vec![SPHERE1],
+            boards: vec![],
+            fog: None,
+        }
+    }
+
+    // a film with one sample per pixel, at its center so that the box filter
+    // does not mix neighbouring pixels. With a surface, the AOVs record hits
+    // with the surface's normal and depth at that pixel.
+    fn film(
+        color: impl Fn(usize, usize) -> Color,
+        surface: Option<&dyn Fn(usize, usize) -> (Vec3, Float)>,
+    ) -> Film {
+        let mut film = Film::new(SIZE, SIZE, 0, SIZE, 0, SIZE, Filter::Box);
+        let scene = scene();
+        if surface.is_some() {
+            film.enable_aovs();
+        }
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let hit = surface.map(|surface| {
+                    let (normal, distance) = surface(x, y);
+                    SurfaceHit {
+                        object_id: 0,
+                        t: distance,
+                        distance,
+                        time: 0.,
+                        point: VEC3_ZERO,
+                        normal,
+                        options: WHITE_OPTIONS,
+                    }
+                });
+                let sample = ShadingSample {
+                    color: color(x, y),
+                    direct: COLOR_ZERO,
+                    indirect: COLOR_ZERO,
+                    hit,
+                };
+                film.add_shading_sample(x as Float + 0.5, y as Float + 0.5, &sample, &scene);
+            }
+        }
+        film
+    }
+
+    fn flat(_: usize, _: usize) -> (Vec3, Float) {
+        (
+            Vec3 {
+                x: 0.,
+                y: 0.,
+                z: 1.,
+            },
+            2.,
+        )
+    }
+
+    // two planes meeting at x = SIZE / 2, at different depths and facing
+    // different directions
+    fn edge(x: usize, _: usize) -> (Vec3, Float) {
+        if x < SIZE / 2 {
+            flat(x, 0)
+        } else {
+            (
+                Vec3 {
+                    x: 1.,
+                    y: 0.,
+                    z: 0.,
+                },
+                6.,
+            )
+        }
+    }
+
+    fn noise(seed: u64) -> impl Fn(usize, usize) -> Color {
+        let mut rng = Rng::new(seed);
+        let values: Vec<Float> = (0..SIZE * SIZE).map(|_| rng.next_float()).collect();
+        move |x, y| grey(0.2 + 0.6 * values[y * SIZE + x])
+    }
+
+    fn variance(pixels: &[Color]) -> Float {
+        let mean = pixels.iter().map(|c| c.g).sum::<Float>() / pixels.len() as Float;
+        pixels.iter().map(|c| (c.g - mean).powi(2)).sum::<Float>() / pixels.len() as Float
+    }
+
+    #[test]
+    fn flat_image_is_unchanged() {
+        let surface: &dyn Fn(usize, usize) -> (Vec3, Float) = &flat;
+        for surface in [None, Some(surface)] {
+            let mut film = film(|_, _| grey(0.3), surface);
+            denoise(&mut film, &DEFAULT_DENOISE_SETTINGS);
+            for pixel in film.resolve() {
+                assert!((pixel.g - 0.3).abs() < 1e-5, "{:?}", pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn noise_is_reduced_on_flat_albedo() {
+        let mut film = film(noise(3), Some(&flat));
+        let before = film.resolve();
+        denoise(&mut film, &DEFAULT_DENOISE_SETTINGS);
+        let after = film.resolve();
+
+        assert!(variance(&after) < 0.1 * variance(&before));
+        let mean = |pixels: &[Color]| pixels.iter().map(|c| c.g).sum::<Float>();
+        assert!((mean(&after) - mean(&before)).abs() / mean(&before) < 0.05);
+    }
+
+    // the planes have similar colors, only the normals and depths keep them
+    // from being blurred into each other
+    #[test]
+    fn edges_in_normal_and_depth_are_preserved() {
+        let color = |x: usize, _: usize| grey(if x < SIZE / 2 { 0.4 } else { 0.5 });
+        let mut film = film(color, Some(&edge));
+        denoise(&mut film, &DEFAULT_DENOISE_SETTINGS);
+        for y in 0..SIZE {
+            assert!((film.pixel(SIZE / 2 - 1, y).g - 0.4).abs() < 1e-3);
+            assert!((film.pixel(SIZE / 2, y).g - 0.5).abs() < 1e-3);
+        }
+
+        // guided by the colors alone, the edge is blurred
+        let mut unguided = self::film(color, None);
+        denoise(&mut unguided, &DEFAULT_DENOISE_SETTINGS);
+        assert!((unguided.pixel(SIZE / 2 - 1, 0).g - 0.4).abs() > 0.01);
+    }
+
+    // noise is reduced on both sides of an edge without leaking across it
+    #[test]
+    fn noisy_edges_are_preserved() {
+        let noise = noise(5);
+        let color = |x: usize, y: usize| {
+            let offset = if x < SIZE / 2 { 0. } else { 0.5 };
+            grey(offset + 0.25 * noise(x, y).g)
+        };
+        let mut film = film(color, Some(&edge));
+        denoise(&mut film, &DEFAULT_DENOISE_SETTINGS);
+        for (i, pixel) in film.resolve().iter().enumerate() {
+            if i % SIZE < SIZE / 2 {
+                assert!(pixel.g < 0.3, "{:?}", pixel);
+            } else {
+                assert!(pixel.g > 0.5, "{:?}", pixel);
+            }
+        }
+    }
+
+    // a single bright pixel spreads exactly as far as the footprint
+    #[test]
+    fn footprint() {
+        let settings = DenoiseSettings {
+            iterations: 3,
+            sigma_color: 1e6,
+            ..DEFAULT_DENOISE_SETTINGS
+        };
+        assert_eq!(settings.footprint(), 14);
+        assert_eq!(DEFAULT_DENOISE_SETTINGS.footprint(), 62);
+
+        let center = SIZE / 2;
+        let mut film = film(
+            |x, y| grey(if (x, y) == (center, center) { 1. } else { 0. }),
+            None,
+        );
+        denoise(&mut film, &settings);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let reached = x.abs_diff(center).max(y.abs_diff(center)) <= settings.footprint();
+                assert_eq!(film.pixel(x, y).g > 0., reached, "{} {}", x, y);
+            }
+        }
+    }
+}
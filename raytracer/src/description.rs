@@ -0,0 +1,359 @@
+use serde::{Deserialize, Serialize};
+
+use super::animation::*;
+use super::camera::*;
+use super::color::*;
+use super::media::*;
+use super::primitives::*;
+use super::scene::*;
+use super::structs::*;
+
+// SCENE FILES
+// scenes are described in JSON, with the fields of Scene next to a camera:
+//
+// {
+//   "camera": { "position": { "x": 0, "y": 2.5, "z": 3 }, "rotation": { "x": 50, "y": 0, "z": 0 } },
+//   "lights": [{ "pos": { ... }, "color": { "r": 1, "g": 1, "b": 1, "a": 1 } }],
+//   "spheres": [{ "center": { ... }, "radius": 1, "options": { "base_color": { ... } } }],
+//   "boards": [{ "axis": "ZAxis", "pos": { ... }, "radius": 2, "grid_size": 0.25, "options": {} }],
+//   "fog": { "sigma_a": { ... }, "sigma_s": { ... }, "phase": { "HenyeyGreenstein": 0.3 } }
+// }
+//
+// shading options default to OPTIONS field by field, spheres, boards and fog
+// can be left out, unknown fields are errors. Every value apart from board
+// axes can be animated with keyframes (see animation.rs), e.g. a sphere
+// moving back and forth:
+//
+//   "center": { "keyframes": [
+//     { "time": 0, "value": { "x": -1, "y": 0, "z": 4 } },
+//     { "time": 2, "value": { "x": 1, "y": 0, "z": 4 }, "interpolation": "Bezier" },
+//     { "time": 4, "value": { "x": -1, "y": 0, "z": 4 }, "interpolation": "Bezier" }
+//   ], "repeat": true }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub camera: CameraDescription,
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub spheres: Vec<SphereDescription>,
+    #[serde(default)]
+    pub boards: Vec<BoardDescription>,
+    #[serde(default)]
+    pub fog: Animated<Option<Medium>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub position: Animated<Vec3>,
+    // rotation in degrees around the x, y and z axis, applied in this order
+    // to a camera looking along +z with +y pointing up
+    pub rotation: Animated<Vec3>,
+}
+
+impl Default for CameraDescription {
+    fn default() -> CameraDescription {
+        CameraDescription {
+            position: Animated::Constant(VEC3_ZERO),
+            rotation: Animated::Constant(VEC3_ZERO),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightDescription {
+    pub pos: Animated<Vec3>,
+    pub color: Animated<Color>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SphereDescription {
+    pub center: Animated<Vec3>,
+    pub radius: Animated<Float>,
+    #[serde(default)]
+    pub options: Animated<ShadingOptions>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardDescription {
+    pub axis: Axis,
+    pub pos: Animated<Vec3>,
+    pub radius: Animated<Float>,
+    pub grid_size: Animated<Float>,
+    #[serde(default)]
+    pub options: Animated<ShadingOptions>,
+}
+
+impl SceneDescription {
+    // parses and validates a scene file, errors are readable messages
+    pub fn from_json(json: &str) -> Result<SceneDescription, String> {
+        let description: SceneDescription =
+            serde_json::from_str(json).map_err(|e| format!("invalid scene file: {}", e))?;
+        description.validate()?;
+        Ok(description)
+    }
+
+    // cannot fail, all maps in a description have string keys and serde_json
+    // writes non-finite numbers as null (which from_json then rejects)
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("scene descriptions serialize to JSON")
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let keyframes = |name: String, results: &[Result<(), String>]| {
+            for result in results {
+                result.clone().map_err(|e| format!("{}: {}", name, e))?;
+            }
+            Ok::<(), String>(())
+        };
+        let positive = |name: String, values: Vec<&Float>| {
+            if values.iter().any(|&&value| value <= 0.) {
+                return Err(format!("{} must be positive", name));
+            }
+            Ok(())
+        };
+
+        keyframes(
+            "camera".to_string(),
+            &[
+                self.camera.position.validate(),
+                self.camera.rotation.validate(),
+            ],
+        )?;
+        for (i, light) in self.lights.iter().enumerate() {
+            keyframes(
+                format!("light {}", i),
+                &[light.pos.validate(), light.color.validate()],
+            )?;
+        }
+        for (i, sphere) in self.spheres.iter().enumerate() {
+            keyframes(
+                format!("sphere {}", i),
+                &[
+                    sphere.center.validate(),
+                    sphere.radius.validate(),
+                    sphere.options.validate(),
+                ],
+            )?;
+            positive(format!("sphere {}: radius", i), sphere.radius.values())?;
+        }
+        for (i, board) in self.boards.iter().enumerate() {
+            keyframes(
+                format!("board {}", i),
+                &[
+                    board.pos.validate(),
+                    board.radius.validate(),
+                    board.grid_size.validate(),
+                    board.options.validate(),
+                ],
+            )?;
+            positive(format!("board {}: radius", i), board.radius.values())?;
+            positive(format!("board {}: grid_size", i), board.grid_size.values())?;
+        }
+        keyframes("fog".to_string(), &[self.fog.validate()])
+    }
+
+    // the scene as it is at time t, spheres and boards keep their paths so
+    // that rays at other times within the shutter see them where they are
+    // at that time, lights stand still
+    pub fn at(&self, t: Float) -> Scene {
+        Scene {
+            lights: self
+                .lights
+                .iter()
+                .map(|light| LightSource {
+                    pos: light.pos.at(t),
+                    color: light.color.at(t),
+                })
+                .collect(),
+            spheres: self
+                .spheres
+                .iter()
+                .map(|sphere| Sphere {
+                    center: sphere.center.at(t),
+                    radius: sphere.radius.at(t),
+                    options: sphere.options.at(t),
+                    motion: Motion {
+                        path: sphere.center.clone(),
+                        time: t,
+                    },
+                })
+                .collect(),
+            boards: self
+                .boards
+                .iter()
+                .map(|board| Checkerboard {
+                    axis: board.axis.clone(),
+                    pos: board.pos.at(t),
+                    radius: board.radius.at(t),
+                    grid_size: board.grid_size.at(t),
+                    options: board.options.at(t),
+                    motion: Motion {
+                        path: board.pos.clone(),
+                        time: t,
+                    },
+                })
+                .collect(),
+            fog: self.fog.at(t),
+        }
+    }
+
+    // camera of the scene at time t, moved by the given offset and rotated by
+    // the given angles (in radians) relative to its own orientation
+    #[allow(clippy::too_many_arguments)]
+    pub fn camera(
+        &self,
+        offset: Vec3,
+        x_rot: Float,
+        y_rot: Float,
+        z_rot: Float,
+        fov: Float,
+        width: usize,
+        height: usize,
+        t: Float,
+    ) -> Camera {
+        let rotation = self.camera.rotation.at(t);
+        let orientation = Quat::from_euler(
+            rotation.x.to_radians(),
+            rotation.y.to_radians(),
+            rotation.z.to_radians(),
+        ) * Quat::from_euler(x_rot, y_rot, z_rot);
+        Camera::from_orientation(
+            self.camera.position.at(t) + offset,
+            orientation,
+            fov,
+            width,
+            height,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::*;
+
+    fn scene_file(name: &str) -> String {
+        let path = format!("{}/../web/scenes/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    // a scene with a single sphere, whose fields are given as JSON
+    fn sphere(fields: &str) -> Result<SceneDescription, String> {
+        SceneDescription::from_json(&format!(
+            r#"{{ "lights": [], "spheres": [{{ {} }}] }}"#,
+            fields
+        ))
+    }
+
+    fn board(radius: &str, grid_size: &str) -> Result<SceneDescription, String> {
+        SceneDescription::from_json(&format!(
+            r#"{{ "lights": [], "boards": [{{
+                "axis": "YAxis", "pos": {{ "x": 0, "y": 0, "z": 0 }},
+                "radius": {}, "grid_size": {}
+            }}] }}"#,
+            radius, grid_size
+        ))
+    }
+
+    fn error(result: Result<SceneDescription, String>) -> String {
+        result.expect_err("the scene should be rejected")
+    }
+
+    const CENTER: &str = r#""center": { "x": 0, "y": 0, "z": 4 }"#;
+
+    #[test]
+    fn defaults() {
+        let description = sphere(&format!(r#"{}, "radius": 1"#, CENTER)).unwrap();
+        assert_eq!(description.camera, CameraDescription::default());
+        assert!(description.boards.is_empty());
+        assert_eq!(description.fog, Animated::Constant(None));
+        let scene = description.at(0.);
+        assert_eq!(scene.spheres[0].options, OPTIONS);
+        assert_eq!(scene.spheres[0].radius, 1.);
+    }
+
+    #[test]
+    fn round_trip() {
+        for description in [
+            demo_scene(),
+            SceneDescription::from_json(&scene_file("fog")).unwrap(),
+            SceneDescription::from_json(&scene_file("glass")).unwrap(),
+            SceneDescription::from_json(&scene_file("mirrors")).unwrap(),
+        ] {
+            let json = description.to_json();
+            assert_eq!(SceneDescription::from_json(&json).unwrap(), description);
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let message = error(SceneDescription::from_json("{ \"lights\": [ }"));
+        assert!(message.starts_with("invalid scene file: "), "{}", message);
+        assert!(message.contains("line 1"), "{}", message);
+        // lights are required
+        let message = error(SceneDescription::from_json("{}"));
+        assert!(message.contains("missing field `lights`"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let message = error(sphere(&format!(
+            r#"{}, "radius": 1, "options": {{ "diffuse": 0.5 }}"#,
+            CENTER
+        )));
+        assert!(message.contains("unknown field `diffuse`"), "{}", message);
+        let message = error(sphere(&format!(r#"{}, "radus": 1"#, CENTER)));
+        assert!(message.contains("unknown field `radus`"), "{}", message);
+        let message = error(SceneDescription::from_json(
+            r#"{ "lights": [], "sphere": [] }"#,
+        ));
+        assert!(message.contains("unknown field `sphere`"), "{}", message);
+    }
+
+    #[test]
+    fn sizes_must_be_positive() {
+        for radius in ["0", "-1"] {
+            assert_eq!(
+                error(sphere(&format!(r#"{}, "radius": {}"#, CENTER, radius))),
+                "sphere 0: radius must be positive"
+            );
+        }
+        // also at any keyframe
+        let animated = r#"{ "keyframes": [
+            { "time": 0, "value": 1 }, { "time": 1, "value": 0 }
+        ] }"#;
+        assert_eq!(
+            error(sphere(&format!(r#"{}, "radius": {}"#, CENTER, animated))),
+            "sphere 0: radius must be positive"
+        );
+        assert_eq!(error(board("0", "0.5")), "board 0: radius must be positive");
+        assert_eq!(
+            error(board("2", "-0.5")),
+            "board 0: grid_size must be positive"
+        );
+        assert!(board("2", "0.5").is_ok());
+    }
+
+    #[test]
+    fn keyframe_errors_name_the_object() {
+        let unordered = r#"{ "keyframes": [
+            { "time": 1, "value": 1 }, { "time": 0, "value": 2 }
+        ] }"#;
+        let message = error(sphere(&format!(r#"{}, "radius": {}"#, CENTER, unordered)));
+        assert!(message.starts_with("sphere 0: "), "{}", message);
+
+        // empty lists are rejected while parsing
+        let empty = r#"{ "keyframes": [] }"#;
+        let message = error(sphere(&format!(r#"{}, "radius": {}"#, CENTER, empty)));
+        assert!(
+            message.contains("keyframes must not be empty"),
+            "{}",
+            message
+        );
+    }
+}
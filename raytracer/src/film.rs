@@ -0,0 +1,432 @@
+use wasm_bindgen::prelude::*;
+
+use super::aov::*;
+use super::color::*;
+use super::scene::*;
+use super::structs::*;
+use super::tonemap::*;
+
+// pixel reconstruction filters, samples are splatted onto all pixels whose
+// center lies within the filter radius
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    MitchellNetravali,
+    Lanczos,
+}
+
+impl Filter {
+    pub fn radius(&self) -> Float {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 1.5,
+            Filter::MitchellNetravali => 2.,
+            Filter::Lanczos => 2.,
+        }
+    }
+
+    // weight of a sample at offset (dx, dy) from a pixel center
+    pub fn eval(&self, dx: Float, dy: Float) -> Float {
+        self.eval_1d(dx) * self.eval_1d(dy)
+    }
+
+    fn eval_1d(&self, d: Float) -> Float {
+        let d = d.abs();
+        if d > self.radius() {
+            return 0.;
+        }
+
+        match self {
+            Filter::Box => 1.,
+            Filter::Tent => 1. - d,
+            Filter::Gaussian => {
+                // standard deviation of half a pixel, shifted to reach zero at the radius
+                let gaussian = |x: Float| (-2. * x * x).exp();
+                gaussian(d) - gaussian(self.radius())
+            }
+            Filter::MitchellNetravali => {
+                let (b, c): (Float, Float) = (1. / 3., 1. / 3.);
+                let x = d;
+                if x < 1. {
+                    ((12. - 9. * b - 6. * c) * x * x * x
+                        + (-18. + 12. * b + 6. * c) * x * x
+                        + (6. - 2. * b))
+                        / 6.
+                } else {
+                    ((-b - 6. * c) * x * x * x
+                        + (6. * b + 30. * c) * x * x
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c))
+                        / 6.
+                }
+            }
+            Filter::Lanczos => sinc(d) * sinc(d / self.radius()),
+        }
+    }
+}
+
+fn sinc(x: Float) -> Float {
+    if x < 1e-5 {
+        return 1.;
+    }
+    let px = PI * x;
+    px.sin() / px
+}
+
+// floating point framebuffer for a rectangular region of an image, stores
+// the filter weighted sum of all samples splatted onto each pixel
+#[derive(Clone)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub start_x: usize,
+    pub end_x: usize,
+    pub start_y: usize,
+    pub end_y: usize,
+    pub filter: Filter,
+    pub aovs: Option<AovBuffers>,
+    pixels: Vec<Color>,
+    weights: Vec<Float>,
+}
+
+impl Film {
+    pub fn new(
+        width: usize,
+        height: usize,
+        start_x: usize,
+        end_x: usize,
+        start_y: usize,
+        end_y: usize,
+        filter: Filter,
+    ) -> Film {
+        let size = (end_x - start_x) * (end_y - start_y);
+        Film {
+            width,
+            height,
+            start_x,
+            end_x,
+            start_y,
+            end_y,
+            filter,
+            aovs: None,
+            pixels: vec![COLOR_ZERO; size],
+            weights: vec![0.; size],
+        }
+    }
+
+    // pixels (start_x, end_x, start_y, end_y) whose samples can reach the
+    // region of this film
+    pub fn sample_bounds(&self) -> (usize, usize, usize, usize) {
+        let margin = (self.filter.radius() - 0.5).ceil().max(0.) as usize;
+        (
+            self.start_x.saturating_sub(margin),
+            (self.end_x + margin).min(self.width),
+            self.start_y.saturating_sub(margin),
+            (self.end_y + margin).min(self.height),
+        )
+    }
+
+    // splats a sample at continuous image coordinates, pixel (x, y) covers
+    // [x, x + 1) x [y, y + 1)
+    pub fn add_sample(&mut self, x: Float, y: Float, color: Color) {
+        let radius = self.filter.radius();
+        let min_x = ((x - 0.5 - radius).ceil().max(self.start_x as Float)) as usize;
+        let max_x = ((x - 0.5 + radius).floor() + 1.).min(self.end_x as Float);
+        let min_y = ((y - 0.5 - radius).ceil().max(self.start_y as Float)) as usize;
+        let max_y = ((y - 0.5 + radius).floor() + 1.).min(self.end_y as Float);
+        if max_x < 0. || max_y < 0. {
+            return;
+        }
+
+        for py in min_y..(max_y as usize) {
+            for px in min_x..(max_x as usize) {
+                let weight = self
+                    .filter
+                    .eval(x - (px as Float + 0.5), y - (py as Float + 0.5));
+                if weight == 0. {
+                    continue;
+                }
+                let i = self.index(px, py);
+                self.pixels[i] += color.scale(weight);
+                self.weights[i] += weight;
+            }
+        }
+    }
+
+    // discards all samples
+    pub fn clear(&mut self) {
+        self.pixels.fill(COLOR_ZERO);
+        self.weights.fill(0.);
+        if self.aovs.is_some() {
+            self.enable_aovs();
+        }
+    }
+
+    // records AOVs for all following samples
+    pub fn enable_aovs(&mut self) {
+        self.aovs = Some(AovBuffers::new(
+            self.end_x - self.start_x,
+            self.end_y - self.start_y,
+        ));
+    }
+
+    // splats a shaded sample, and adds it to the AOVs of the pixel it falls into
+    pub fn add_shading_sample(
+        &mut self,
+        x: Float,
+        y: Float,
+        sample: &ShadingSample,
+        scene: &Scene,
+    ) {
+        self.add_sample(x, y, sample.color);
+
+        let (px, py) = (x.floor() as usize, y.floor() as usize);
+        let inside =
+            (self.start_x..self.end_x).contains(&px) && (self.start_y..self.end_y).contains(&py);
+        if inside {
+            let i = self.index(px, py);
+            if let Some(aovs) = &mut self.aovs {
+                aovs.add(i, sample, scene);
+            }
+        }
+    }
+
+    // copies the pixels and AOVs of a film of the same image whose region
+    // lies within this film's region, e.g. to assemble an image from
+    // separately rendered parts
+    pub fn insert(&mut self, other: &Film) {
+        assert!((other.width, other.height) == (self.width, self.height));
+        assert!(other.start_x >= self.start_x && other.end_x <= self.end_x);
+        assert!(other.start_y >= self.start_y && other.end_y <= self.end_y);
+        let len = other.end_x - other.start_x;
+        for y in other.start_y..other.end_y {
+            let (from, to) = (other.index(other.start_x, y), self.index(other.start_x, y));
+            self.pixels[to..to + len].copy_from_slice(&other.pixels[from..from + len]);
+            self.weights[to..to + len].copy_from_slice(&other.weights[from..from + len]);
+        }
+        if let (Some(aovs), Some(other_aovs)) = (&mut self.aovs, &other.aovs) {
+            aovs.insert(
+                other_aovs,
+                other.start_x - self.start_x,
+                other.start_y - self.start_y,
+            );
+        }
+    }
+
+    // reconstructed color of the pixel at image coordinates (x, y)
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let i = self.index(x, y);
+        if self.weights[i] == 0. {
+            return COLOR_ZERO;
+        }
+        self.pixels[i].scale(1. / self.weights[i])
+    }
+
+    // reconstructed colors of the film's region, as rows of pixels
+    pub fn resolve(&self) -> Vec<Color> {
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in self.start_y..self.end_y {
+            for x in self.start_x..self.end_x {
+                pixels.push(self.pixel(x, y));
+            }
+        }
+        pixels
+    }
+
+    // replaces the film's content by already reconstructed colors
+    pub fn set_resolved(&mut self, pixels: Vec<Color>) {
+        self.weights = vec![1.; pixels.len()];
+        self.pixels = pixels;
+    }
+
+    // tone maps the film's region to 8 bit sRGB, as rows of opaque RGBA pixels
+    pub fn develop(&self, tone_mapping: &ToneMapping) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(4 * self.pixels.len());
+        for y in self.start_y..self.end_y {
+            for x in self.start_x..self.end_x {
+                rgba.extend_from_slice(&tone_mapping.to_srgb8(self.pixel(x, y)));
+                rgba.push(255);
+            }
+        }
+        rgba
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y - self.start_y) * (self.end_x - self.start_x) + (x - self.start_x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 5] = [
+        Filter::Box,
+        Filter::Tent,
+        Filter::Gaussian,
+        Filter::MitchellNetravali,
+        Filter::Lanczos,
+    ];
+
+    const GREY: Color = Color {
+        r: 0.25,
+        g: 0.5,
+        b: 0.75,
+        a: 1.,
+    };
+
+    fn assert_close(a: Color, b: Color) {
+        let d = a - b;
+        assert!(
+            d.r.abs() < 1e-4 && d.g.abs() < 1e-4 && d.b.abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn filters_vanish_outside_their_radius() {
+        for filter in FILTERS {
+            let r = filter.radius();
+            assert_eq!(filter.eval(r + 0.01, 0.), 0.);
+            assert_eq!(filter.eval(0., -r - 0.01), 0.);
+            assert!(filter.eval(0., 0.) > 0.);
+        }
+    }
+
+    #[test]
+    fn filters_are_symmetric() {
+        for filter in FILTERS {
+            for d in [0.1, 0.4, 0.9, 1.3] {
+                assert_eq!(filter.eval(d, 0.2), filter.eval(-d, 0.2));
+                assert_eq!(filter.eval(d, 0.2), filter.eval(0.2, d));
+            }
+        }
+    }
+
+    #[test]
+    fn sinc_is_one_at_zero() {
+        assert_eq!(sinc(0.), 1.);
+        assert!((sinc(1e-4) - 1.).abs() < 1e-6);
+        assert!(sinc(1.).abs() < 1e-6);
+        assert_eq!(Filter::Lanczos.eval(0., 0.), 1.);
+    }
+
+    // the weights of all pixels a sample reaches sum to one, wherever the
+    // sample falls
+    #[test]
+    fn filters_are_a_partition_of_unity() {
+        for filter in [Filter::Tent, Filter::MitchellNetravali] {
+            for offset in [0., 0.1, 0.25, 0.5, 0.8] {
+                let sum: Float = (-3..=3).map(|k| filter.eval_1d(offset + k as Float)).sum();
+                assert!((sum - 1.).abs() < 1e-5, "{:?} sums to {}", filter, sum);
+            }
+        }
+    }
+
+    // the weighted sum is normalized, a constant image stays constant with
+    // every filter
+    #[test]
+    fn constant_image_is_reconstructed() {
+        for filter in FILTERS {
+            let mut film = Film::new(8, 6, 0, 8, 0, 6, filter);
+            for y in 0..24 {
+                for x in 0..32 {
+                    film.add_sample((x as Float + 0.5) / 4., (y as Float + 0.5) / 4., GREY);
+                }
+            }
+            for pixel in film.resolve() {
+                assert_close(pixel, GREY);
+            }
+        }
+    }
+
+    #[test]
+    fn pixels_without_samples_are_black() {
+        let film = Film::new(4, 4, 0, 4, 0, 4, Filter::Gaussian);
+        assert!(film.resolve().iter().all(|&pixel| pixel == COLOR_ZERO));
+    }
+
+    // samples near and past the film edges only reach pixels inside the
+    // region
+    #[test]
+    fn splats_near_the_edges_stay_in_bounds() {
+        for filter in FILTERS {
+            let mut film = Film::new(16, 16, 4, 8, 4, 8, filter);
+            for (x, y) in [
+                (0.01, 0.01),
+                (-3., 5.),
+                (4.01, 3.99),
+                (7.99, 8.01),
+                (15.99, 15.99),
+                (20., 6.),
+                (6., -0.5),
+            ] {
+                film.add_sample(x, y, GREY);
+            }
+            film.add_sample(4.2, 4.2, COLOR_WHITE);
+            assert_eq!(film.resolve().len(), 16);
+            assert!(film.pixel(4, 4).r > 0.);
+            assert_eq!(film.pixel(7, 4), COLOR_ZERO);
+        }
+    }
+
+    // a film assembled from films of its parts equals the film rendered as
+    // a whole
+    #[test]
+    fn films_are_assembled_from_parts() {
+        let scene = Scene {
+            lights: vec![],
+            spheres: vec![],
+            boards: vec![],
+            fog: None,
+        };
+        let new_film = |start_x, end_x, start_y, end_y| {
+            let mut film = Film::new(12, 10, start_x, end_x, start_y, end_y, Filter::Gaussian);
+            film.enable_aovs();
+            film
+        };
+        let mut whole = new_film(2, 10, 1, 9);
+        let mut parts = [
+            new_film(2, 10, 1, 4),
+            new_film(2, 6, 4, 9),
+            new_film(6, 10, 4, 9),
+        ];
+        for i in 0..400 {
+            let (x, y) = ((i % 20) as Float * 0.6, (i / 20) as Float * 0.5);
+            let sample = ShadingSample {
+                color: GREY.scale(x + y),
+                direct: GREY.scale(y),
+                indirect: COLOR_ZERO,
+                hit: None,
+            };
+            whole.add_shading_sample(x, y, &sample, &scene);
+            for part in &mut parts {
+                part.add_shading_sample(x, y, &sample, &scene);
+            }
+        }
+
+        let mut assembled = new_film(2, 10, 1, 9);
+        for part in &parts {
+            assembled.insert(part);
+        }
+        assert_eq!(assembled.resolve(), whole.resolve());
+        let (aovs, whole_aovs) = (assembled.aovs.unwrap(), whole.aovs.unwrap());
+        for i in 0..8 * 8 {
+            assert_eq!(aovs.direct(i), whole_aovs.direct(i));
+        }
+    }
+
+    #[test]
+    fn sample_bounds_cover_the_filter_radius() {
+        let film = Film::new(16, 16, 4, 8, 0, 4, Filter::MitchellNetravali);
+        assert_eq!(film.sample_bounds(), (2, 10, 0, 6));
+        let film = Film::new(16, 16, 4, 8, 12, 16, Filter::Box);
+        assert_eq!(film.sample_bounds(), (4, 8, 12, 16));
+    }
+}
@@ -1,6 +1,19 @@
+pub mod adaptive;
+pub mod animation;
+pub mod aov;
 pub mod camera;
 pub mod color;
+pub mod demo;
+pub mod denoise;
+pub mod description;
+pub mod film;
+pub mod media;
+pub mod output;
+pub mod packet;
 pub mod primitives;
+pub mod progressive;
+pub mod render;
 pub mod sampling;
 pub mod scene;
 pub mod structs;
+pub mod tonemap;
@@ -0,0 +1,306 @@
+use super::color::*;
+use super::sampling::*;
+use super::structs::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PhaseFunction {
+    Isotropic,
+    // asymmetry parameter g in (-1, 1), positive values scatter forward
+    HenyeyGreenstein(Float),
+}
+
+impl PhaseFunction {
+    // evaluates the phase function for the cosine of the angle between the
+    // direction the light travels in and the direction it is scattered to
+    //
+    // normalized relative to isotropic scattering (which returns 1), so media
+    // are lit on the same scale as surfaces by the unitless light sources
+    pub fn eval(&self, cos_theta: Float) -> Float {
+        match *self {
+            PhaseFunction::Isotropic => 1.,
+            PhaseFunction::HenyeyGreenstein(g) => {
+                let denom = 1. + g * g - 2. * g * cos_theta;
+                (1. - g * g) / (denom * denom.max(0.).sqrt())
+            }
+        }
+    }
+}
+
+// homogeneous participating medium, coefficients are given per unit length
+// and per color channel (alpha is ignored)
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Medium {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    pub phase: PhaseFunction,
+}
+
+pub enum FreeFlight {
+    // the ray scattered inside the medium after distance t
+    Scattered(Float, Color),
+    // the ray passed through the medium up to the end of the segment
+    Passed(Color),
+}
+
+impl Medium {
+    // fog with the given extinction density, albedo (fraction of extinction
+    // that is scattered) and Henyey-Greenstein asymmetry
+    pub fn fog(density: Float, albedo: Color, g: Float) -> Medium {
+        Medium {
+            sigma_a: Color {
+                r: density * (1. - albedo.r),
+                g: density * (1. - albedo.g),
+                b: density * (1. - albedo.b),
+                a: 0.,
+            },
+            sigma_s: albedo.scale(density),
+            phase: if g == 0. {
+                PhaseFunction::Isotropic
+            } else {
+                PhaseFunction::HenyeyGreenstein(g)
+            },
+        }
+    }
+
+    pub fn sigma_t(&self) -> Color {
+        self.sigma_a + self.sigma_s
+    }
+
+    // fraction of light that passes through a segment of the given length
+    pub fn transmittance(&self, distance: Float) -> Color {
+        let sigma_t = self.sigma_t();
+        Color {
+            r: attenuation(sigma_t.r, distance),
+            g: attenuation(sigma_t.g, distance),
+            b: attenuation(sigma_t.b, distance),
+            a: 1.,
+        }
+    }
+
+    // samples a free-flight distance along a segment of length t_max
+    //
+    // distances are sampled proportional to the extinction of a randomly
+    // picked channel, the returned weight is the transmittance (and, if the
+    // ray scattered, the scattering coefficient) divided by the pdf averaged
+    // over all channels
+    pub fn sample_distance(&self, t_max: Float, rng: &mut Rng) -> FreeFlight {
+        let sigma_t = self.sigma_t();
+        let channel_sigma = match (rng.next_float() * 3.) as usize {
+            0 => sigma_t.r,
+            1 => sigma_t.g,
+            _ => sigma_t.b,
+        };
+        let distance = if channel_sigma > 0. {
+            -(1. - rng.next_float()).ln() / channel_sigma
+        } else {
+            Float::INFINITY
+        };
+
+        let t = distance.min(t_max);
+        let tr = self.transmittance(t);
+        if distance < t_max {
+            let pdf = (sigma_t.r * tr.r + sigma_t.g * tr.g + sigma_t.b * tr.b) / 3.;
+            FreeFlight::Scattered(
+                t,
+                Color {
+                    r: tr.r * self.sigma_s.r / pdf,
+                    g: tr.g * self.sigma_s.g / pdf,
+                    b: tr.b * self.sigma_s.b / pdf,
+                    a: 1.,
+                },
+            )
+        } else {
+            let pdf = (tr.r + tr.g + tr.b) / 3.;
+            if pdf == 0. {
+                return FreeFlight::Passed(COLOR_BLACK);
+            }
+            FreeFlight::Passed(Color {
+                r: tr.r / pdf,
+                g: tr.g / pdf,
+                b: tr.b / pdf,
+                a: 1.,
+            })
+        }
+    }
+}
+
+// exp(-sigma * distance), without producing NaN for empty channels along
+// infinitely long segments
+fn attenuation(sigma: Float, distance: Float) -> Float {
+    if sigma == 0. {
+        1.
+    } else {
+        (-sigma * distance).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a reddish fog absorbing blue most and scattering most of red and green
+    fn medium() -> Medium {
+        Medium {
+            sigma_a: Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.8,
+                a: 0.,
+            },
+            sigma_s: Color {
+                r: 0.9,
+                g: 0.5,
+                b: 0.2,
+                a: 0.,
+            },
+            phase: PhaseFunction::Isotropic,
+        }
+    }
+
+    fn channels(c: Color) -> [Float; 3] {
+        [c.r, c.g, c.b]
+    }
+
+    #[test]
+    fn fog_coefficients() {
+        let albedo = Color {
+            r: 1.,
+            g: 0.5,
+            b: 0.,
+            a: 1.,
+        };
+        let fog = Medium::fog(2., albedo, 0.);
+        assert_eq!(channels(fog.sigma_t()), [2., 2., 2.]);
+        assert_eq!(channels(fog.sigma_s), [2., 1., 0.]);
+        assert_eq!(fog.phase, PhaseFunction::Isotropic);
+        assert_eq!(
+            Medium::fog(1., albedo, 0.5).phase,
+            PhaseFunction::HenyeyGreenstein(0.5)
+        );
+    }
+
+    #[test]
+    fn transmittance_follows_beer_lambert() {
+        let medium = medium();
+        assert_eq!(channels(medium.transmittance(0.)), [1., 1., 1.]);
+        for distance in [0.5, 1., 3.] {
+            let tr = channels(medium.transmittance(distance));
+            let sigma_t = channels(medium.sigma_t());
+            for (tr, sigma_t) in tr.iter().zip(sigma_t) {
+                assert!((tr - (-sigma_t * distance).exp()).abs() < 1e-6);
+            }
+            // attenuation multiplies along a segment
+            let half = medium.transmittance(distance / 2.);
+            for (tr, half) in tr.iter().zip(channels(half * half)) {
+                assert!((tr - half).abs() < 1e-6);
+            }
+        }
+        // empty channels pass everything, even infinitely far
+        let clear = Medium::fog(0., COLOR_WHITE, 0.);
+        assert_eq!(channels(clear.transmittance(Float::INFINITY)), [1., 1., 1.]);
+        assert_eq!(
+            channels(medium.transmittance(Float::INFINITY)),
+            [0., 0., 0.]
+        );
+    }
+
+    // sums of the weights of passed and scattered rays over many samples
+    fn mean_weights(medium: &Medium, t_max: Float, n: usize) -> (Color, Color) {
+        let mut rng = Rng::new(7);
+        let (mut passed, mut scattered) = (COLOR_ZERO, COLOR_ZERO);
+        for _ in 0..n {
+            match medium.sample_distance(t_max, &mut rng) {
+                FreeFlight::Scattered(t, weight) => {
+                    assert!((0. ..t_max).contains(&t));
+                    scattered += weight;
+                }
+                FreeFlight::Passed(weight) => passed += weight,
+            }
+        }
+        (
+            passed.scale(1. / n as Float),
+            scattered.scale(1. / n as Float),
+        )
+    }
+
+    // on average passed rays carry the transmittance of the segment and
+    // scattered ones the fraction of light scattered along it, per channel
+    #[test]
+    fn sample_distance_is_unbiased() {
+        let medium = medium();
+        for t_max in [0.5, 2., Float::INFINITY] {
+            let (passed, scattered) = mean_weights(&medium, t_max, 200_000);
+            let tr = channels(medium.transmittance(t_max));
+            let sigma_s = channels(medium.sigma_s);
+            let sigma_t = channels(medium.sigma_t());
+            for c in 0..3 {
+                let expected = sigma_s[c] / sigma_t[c] * (1. - tr[c]);
+                assert!((channels(passed)[c] - tr[c]).abs() < 0.01, "{}", t_max);
+                assert!(
+                    (channels(scattered)[c] - expected).abs() < 0.01,
+                    "{}",
+                    t_max
+                );
+            }
+        }
+    }
+
+    // all light entering an infinite non-absorbing medium is scattered
+    #[test]
+    fn scattering_conserves_energy() {
+        let fog = Medium::fog(0.7, COLOR_WHITE, 0.);
+        let (passed, scattered) = mean_weights(&fog, Float::INFINITY, 10_000);
+        assert_eq!(channels(passed), [0., 0., 0.]);
+        for c in channels(scattered) {
+            assert!((c - 1.).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn empty_segments_pass_everything() {
+        let clear = Medium::fog(0., COLOR_WHITE, 0.);
+        let mut rng = Rng::new(1);
+        for t_max in [1., Float::INFINITY] {
+            match clear.sample_distance(t_max, &mut rng) {
+                FreeFlight::Passed(weight) => assert_eq!(channels(weight), [1., 1., 1.]),
+                FreeFlight::Scattered(..) => panic!("scattered in an empty medium"),
+            }
+        }
+    }
+
+    // integral over the sphere of directions divided by 4 pi, by the midpoint
+    // rule over the cosine
+    fn sphere_average(f: impl Fn(Float) -> Float) -> Float {
+        let n = 100_000;
+        (0..n)
+            .map(|i| f(-1. + (2 * i + 1) as Float / n as Float))
+            .sum::<Float>()
+            / n as Float
+    }
+
+    #[test]
+    fn phase_functions_are_normalized() {
+        assert_eq!(PhaseFunction::Isotropic.eval(0.3), 1.);
+        for cos_theta in [-1., 0., 0.5, 1.] {
+            assert_eq!(PhaseFunction::HenyeyGreenstein(0.).eval(cos_theta), 1.);
+        }
+        for g in [-0.7, -0.2, 0.3, 0.8] {
+            let phase = PhaseFunction::HenyeyGreenstein(g);
+            assert!(
+                (sphere_average(|c| phase.eval(c)) - 1.).abs() < 1e-3,
+                "{}",
+                g
+            );
+            // g is the mean cosine of the scattering angle
+            assert!(
+                (sphere_average(|c| c * phase.eval(c)) - g).abs() < 1e-3,
+                "{}",
+                g
+            );
+            // forward scattering for positive g
+            assert_eq!(phase.eval(1.) > phase.eval(-1.), g > 0.);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::io::{self, Read, Write};
+
+use super::color::*;
+use super::structs::*;
+
+// IMAGE FILES
+// writers for the native renderer, all images are given as rows of pixels
+// from top to bottom
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PixelType {
+    Half,
+    Float,
+}
+
+// a single named channel of an OpenEXR image, layers are expressed by
+// prefixing channel names, e.g. "diffuse.R"
+pub struct ExrChannel {
+    pub name: String,
+    pub values: Vec<Float>,
+}
+
+// R, G, B and A channels of a layer, the unnamed layer is the beauty image
+pub fn color_channels(layer: &str, pixels: &[Color]) -> Vec<ExrChannel> {
+    let prefix = if layer.is_empty() {
+        String::new()
+    } else {
+        format!("{}.", layer)
+    };
+    let channel = |name: &str, value: fn(&Color) -> Float| ExrChannel {
+        name: format!("{}{}", prefix, name),
+        values: pixels.iter().map(value).collect(),
+    };
+    vec![
+        channel("R", |c| c.r),
+        channel("G", |c| c.g),
+        channel("B", |c| c.b),
+        channel("A", |c| c.a),
+    ]
+}
+
+// uncompressed scanline OpenEXR file
+pub fn write_exr<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    channels: &[ExrChannel],
+    pixel_type: PixelType,
+) -> io::Result<()> {
+    for channel in channels {
+        if channel.values.len() != width * height {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("channel {} does not match the image size", channel.name),
+            ));
+        }
+    }
+    // channels have to be stored in alphabetical order
+    let mut channels: Vec<&ExrChannel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let (type_id, bytes_per_value): (i32, usize) = match pixel_type {
+        PixelType::Half => (1, 2),
+        PixelType::Float => (2, 4),
+    };
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&20000630i32.to_le_bytes());
+    header.extend_from_slice(&2i32.to_le_bytes());
+
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&type_id.to_le_bytes());
+        channel_list.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    exr_attribute(&mut header, "channels", "chlist", &channel_list);
+
+    exr_attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // offset table with one entry per scanline, followed by the scanlines
+    let line_size = channels.len() * width * bytes_per_value;
+    let first_line = (header.len() + 8 * height) as u64;
+    for y in 0..height {
+        let offset = first_line + (y * (8 + line_size)) as u64;
+        header.extend_from_slice(&offset.to_le_bytes());
+    }
+    writer.write_all(&header)?;
+
+    let mut line = Vec::with_capacity(8 + line_size);
+    for y in 0..height {
+        line.clear();
+        line.extend_from_slice(&(y as i32).to_le_bytes());
+        line.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for channel in &channels {
+            for &v in &channel.values[y * width..(y + 1) * width] {
+                // EXR floats are single precision regardless of Float
+                #[allow(clippy::unnecessary_cast)]
+                let v = v as f32;
+                match pixel_type {
+                    PixelType::Half => line.extend_from_slice(&f32_to_f16(v).to_le_bytes()),
+                    PixelType::Float => line.extend_from_slice(&v.to_le_bytes()),
+                }
+            }
+        }
+        writer.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// IEEE 754 half precision bits, rounding to nearest even
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7fffff;
+
+    if exponent == 0xff {
+        // infinity or NaN
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // subnormal or zero
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x800000;
+        let shift = (14 - half_exponent) as u32;
+        let half_mantissa = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = remainder > halfway || (remainder == halfway && half_mantissa & 1 == 1);
+        return sign | (half_mantissa + round as u32) as u16;
+    }
+
+    let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    // rounding may carry into the exponent, which correctly produces infinity
+    sign | (half + round as u32) as u16
+}
+
+// Radiance RGBE (.hdr) file with uncompressed scanlines
+pub fn write_hdr<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[Color],
+) -> io::Result<()> {
+    if pixels.len() != width * height {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "pixels do not match the image size",
+        ));
+    }
+    write!(
+        writer,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+
+    let mut data = Vec::with_capacity(4 * width * height);
+    for c in pixels {
+        let max = c.r.max(c.g).max(c.b);
+        if max < 1e-32 {
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            continue;
+        }
+        // max = m * 2^e with m in [0.5, 1)
+        let e = max.log2().floor() as i32 + 1;
+        let scale = 256. / (2. as Float).powi(e);
+        data.extend_from_slice(&[
+            (c.r.max(0.) * scale) as u8,
+            (c.g.max(0.) * scale) as u8,
+            (c.b.max(0.) * scale) as u8,
+            (e + 128) as u8,
+        ]);
+    }
+    writer.write_all(&data)
+}
+
+// 8 bit RGBA PNG
+pub fn write_png<W: Write>(writer: W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut png_writer = encoder.write_header()?;
+    png_writer.write_image_data(rgba)?;
+    png_writer.finish()?;
+    Ok(())
+}
+
+// reads back an 8 bit RGBA PNG as written by write_png, returns its width,
+// height and pixels
+pub fn read_png<R: Read>(reader: R) -> io::Result<(usize, usize, Vec<u8>)> {
+    let invalid = |e: png::DecodingError| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut png_reader = png::Decoder::new(reader).read_info().map_err(invalid)?;
+    let mut rgba = vec![0; png_reader.output_buffer_size()];
+    let info = png_reader.next_frame(&mut rgba).map_err(invalid)?;
+    if (info.color_type, info.bit_depth) != (png::ColorType::Rgba, png::BitDepth::Eight) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an 8 bit RGBA image",
+        ));
+    }
+    rgba.truncate(info.buffer_size());
+    Ok((info.width as usize, info.height as usize, rgba))
+}
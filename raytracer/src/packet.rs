@@ -0,0 +1,134 @@
+use std::ops::{Add, Index, IndexMut, Sub};
+
+use wide::*;
+// the comparisons of FloatV
+pub use wide::{CmpEq, CmpGe, CmpGt, CmpLe, CmpLt};
+
+use super::structs::*;
+
+// RAY PACKETS
+// coherent rays, like the primary rays of neighbouring samples, are
+// intersected together with every ray in one lane of a SIMD vector. Uses
+// SSE/AVX or NEON natively and simd128 on wasm if the target features are
+// enabled, otherwise falls back to scalar code.
+
+#[cfg(not(feature = "f64"))]
+pub type FloatV = f32x8;
+#[cfg(feature = "f64")]
+pub type FloatV = f64x4;
+
+// number of rays in a packet
+pub const LANES: usize = std::mem::size_of::<FloatV>() / std::mem::size_of::<Float>();
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vec3V {
+    pub x: FloatV,
+    pub y: FloatV,
+    pub z: FloatV,
+}
+
+impl Vec3V {
+    // the same vector in every lane
+    pub fn splat(v: Vec3) -> Vec3V {
+        Vec3V {
+            x: FloatV::splat(v.x),
+            y: FloatV::splat(v.y),
+            z: FloatV::splat(v.z),
+        }
+    }
+
+    pub fn scale(self, s: FloatV) -> Vec3V {
+        Vec3V {
+            x: s * self.x,
+            y: s * self.y,
+            z: s * self.z,
+        }
+    }
+
+    pub fn norm(self) -> FloatV {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    pub fn dot(self, other: Vec3V) -> FloatV {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+}
+
+impl Add for Vec3V {
+    type Output = Vec3V;
+    fn add(self, other: Vec3V) -> Vec3V {
+        Vec3V {
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+impl Sub for Vec3V {
+    type Output = Vec3V;
+    fn sub(self, other: Vec3V) -> Vec3V {
+        Vec3V {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
+
+// coordinates by axis index, 0 is x, 1 is y and 2 is z
+impl Index<usize> for Vec3V {
+    type Output = FloatV;
+    fn index(&self, axis: usize) -> &FloatV {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("axis {} out of range", axis),
+        }
+    }
+}
+
+impl IndexMut<usize> for Vec3V {
+    fn index_mut(&mut self, axis: usize) -> &mut FloatV {
+        match axis {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("axis {} out of range", axis),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RayPacket {
+    pub origin: Vec3V,
+    pub direction: Vec3V,
+    pub time: FloatV,
+}
+
+impl RayPacket {
+    // packs up to LANES rays, the remaining lanes repeat the last ray
+    pub fn new(rays: &[Ray]) -> RayPacket {
+        assert!(!rays.is_empty() && rays.len() <= LANES);
+        let lane = |i: usize| &rays[i.min(rays.len() - 1)];
+        let lanes = |f: &dyn Fn(&Ray) -> Float| FloatV::new(std::array::from_fn(|i| f(lane(i))));
+        RayPacket {
+            origin: Vec3V {
+                x: lanes(&|ray| ray.origin.x),
+                y: lanes(&|ray| ray.origin.y),
+                z: lanes(&|ray| ray.origin.z),
+            },
+            direction: Vec3V {
+                x: lanes(&|ray| ray.direction.x),
+                y: lanes(&|ray| ray.direction.y),
+                z: lanes(&|ray| ray.direction.z),
+            },
+            time: lanes(&|ray| ray.time),
+        }
+    }
+
+    pub fn at(&self, t: FloatV) -> Vec3V {
+        self.origin + self.direction.scale(t)
+    }
+}
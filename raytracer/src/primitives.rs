@@ -1,10 +1,15 @@
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+use super::animation::*;
 use super::color::*;
+use super::media::*;
+use super::packet::*;
 use super::structs::*;
 
 #[wasm_bindgen]
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ShadingOptions {
     pub base_color: Color,
     pub ambiant_part: Float,
@@ -13,6 +18,10 @@ pub struct ShadingOptions {
     pub specular_coefficient: Float,
     pub reflective_part: Float,
     pub refraction_index: Float,
+    // if set, the surface bounds a volume filled with this medium and rays
+    // are transmitted through it (refracted if refraction_index is non-zero)
+    #[wasm_bindgen(skip)]
+    pub interior: Option<Medium>,
 }
 
 pub type Intersection = Option<(Float, Vec3, Vec3, ShadingOptions)>;
@@ -21,58 +30,153 @@ pub trait Primitive {
     // computes the intersection with the primitive and
     // returns Some(distance, intersection point, normal vec, shading options) if successful, otherwise None
     fn intersect(&self, ray: &Ray) -> Intersection;
+
+    // distances to the intersections of a packet of rays, computed the same
+    // way as by intersect, infinity in lanes that miss the primitive
+    fn intersect_packet(&self, packet: &RayPacket) -> FloatV;
+
+    // bound on the rounding error in each coordinate of an intersection
+    // point returned by intersect at the given time
+    fn error_bound(&self, point: &Vec3, time: Float) -> Vec3;
+
+    // surface coordinates in [0, 1]^2 of a point on the primitive at the
+    // given time
+    fn uv(&self, point: &Vec3, time: Float) -> (Float, Float);
 }
 
 #[wasm_bindgen]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: Float,
     pub options: ShadingOptions,
+    // movement of the center, for motion blur
+    #[serde(default)]
+    #[wasm_bindgen(skip)]
+    pub motion: Motion,
+}
+
+impl Sphere {
+    pub fn center_at(&self, time: Float) -> Vec3 {
+        self.center + self.motion.offset(time)
+    }
 }
 
 impl Primitive for Sphere {
     fn intersect(&self, ray: &Ray) -> Intersection {
-        let oc = ray.origin - self.center;
+        let center = self.center_at(ray.time);
+        let oc = ray.origin - center;
 
         let a: Float = ray.direction.dot(ray.direction);
-        let b: Float = 2. * ray.direction.dot(oc);
+        let b: Float = ray.direction.dot(oc);
         let c: Float = oc.dot(oc) - self.radius * self.radius;
 
-        let d: Float = b * b - 4. * a * c;
+        // the discriminant b² - ac is computed from the distance between the
+        // center and the ray, which does not cancel out for distant spheres
+        let l = oc - ray.direction.scale(b / a);
+        let d: Float = a * (self.radius * self.radius - l.dot(l));
 
         if d < 0. {
             return None;
         }
 
-        let t: Float = (-b - d.sqrt()) / (2. * a);
-        let intersection: Vec3 = ray.at(t);
-        let normal = (intersection - self.center).normalize();
+        let q = -(b + d.sqrt().copysign(b));
+        if q == 0. {
+            return None;
+        }
+        let (t0, t1) = (q / a, c / q);
+
+        // use the far intersection if the ray starts inside the sphere
+        let mut t: Float = t0.min(t1);
+        if t <= 0. {
+            t = t0.max(t1);
+        }
+
+        // moved onto the surface, leaving only the error of the projection
+        let intersection: Vec3 = ray.at(t) - center;
+        let intersection = center + intersection.scale(self.radius / intersection.norm());
+        let normal = (intersection - center).normalize();
 
         Some((t, intersection, normal, self.options))
     }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> FloatV {
+        let center = Vec3V::splat(self.center) + self.motion.offset_packet(packet.time);
+        let oc = packet.origin - center;
+        let radius2 = FloatV::splat(self.radius * self.radius);
+
+        let a = packet.direction.dot(packet.direction);
+        let b = packet.direction.dot(oc);
+        let c = oc.dot(oc) - radius2;
+
+        let l = oc - packet.direction.scale(b / a);
+        let d = a * (radius2 - l.dot(l));
+        // most packets miss most spheres
+        if d.cmp_ge(FloatV::ZERO).none() {
+            return FloatV::splat(Float::INFINITY);
+        }
+
+        let q = -(b + d.sqrt().copysign(b));
+        let (t0, t1) = (q / a, c / q);
+
+        let near = t0.min(t1);
+        let t = near.cmp_le(FloatV::ZERO).blend(t0.max(t1), near);
+
+        let miss = d.cmp_lt(FloatV::ZERO) | q.cmp_eq(FloatV::ZERO) | t.is_nan();
+        miss.blend(FloatV::splat(Float::INFINITY), t)
+    }
+
+    fn error_bound(&self, point: &Vec3, time: Float) -> Vec3 {
+        (point.abs() + self.center_at(time).abs()).scale(gamma(5))
+    }
+
+    fn uv(&self, point: &Vec3, time: Float) -> (Float, Float) {
+        let n = (*point - self.center_at(time)).normalize();
+        (
+            0.5 + n.z.atan2(n.x) / (2. * PI),
+            n.y.clamp(-1., 1.).acos() / PI,
+        )
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Axis {
     XAxis,
     YAxis,
     ZAxis,
 }
 
+impl Axis {
+    // index of the axis' coordinate in a Vec3
+    pub fn index(&self) -> usize {
+        match self {
+            Axis::XAxis => 0,
+            Axis::YAxis => 1,
+            Axis::ZAxis => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Checkerboard {
     pub axis: Axis,
     pub pos: Vec3,
     pub radius: Float,
     pub grid_size: Float,
     pub options: ShadingOptions,
+    // movement of the board and its pattern, for motion blur
+    #[serde(default)]
+    pub motion: Motion,
 }
 
 impl Primitive for Checkerboard {
     fn intersect(&self, ray: &Ray) -> Intersection {
+        let offset = self.motion.offset(ray.time);
+        let pos = self.pos + offset;
         let ax_pos = match self.axis {
-            Axis::XAxis => self.pos.x,
-            Axis::YAxis => self.pos.y,
-            Axis::ZAxis => self.pos.z,
+            Axis::XAxis => pos.x,
+            Axis::YAxis => pos.y,
+            Axis::ZAxis => pos.z,
         };
 
         // i = o + t d => t = (i - o) / d
@@ -81,7 +185,7 @@ impl Primitive for Checkerboard {
             Axis::YAxis => (ax_pos - ray.origin.y) / ray.direction.y,
             Axis::ZAxis => (ax_pos - ray.origin.z) / ray.direction.z,
         };
-        if t < 0. || t > 1e12 {
+        if !(0. ..=1e12).contains(&t) {
             return None;
         };
 
@@ -103,23 +207,24 @@ impl Primitive for Checkerboard {
             },
         };
 
-        let intersection_point = ray.at(t);
-        if (intersection_point - self.pos).norm() > self.radius {
+        // exactly on the board, which has no error along its axis
+        let mut intersection_point = ray.at(t);
+        intersection_point[self.axis.index()] = ax_pos;
+        if (intersection_point - pos).norm() > self.radius {
             return None;
         };
 
+        // the pattern moves along with the board
+        let p = intersection_point - offset;
         let d = match self.axis {
             Axis::XAxis => {
-                (intersection_point.y / self.grid_size).floor() as i32
-                    + (intersection_point.z / self.grid_size).floor() as i32
+                (p.y / self.grid_size).floor() as i32 + (p.z / self.grid_size).floor() as i32
             }
             Axis::YAxis => {
-                (intersection_point.z / self.grid_size).floor() as i32
-                    + (intersection_point.z / self.grid_size).floor() as i32
+                (p.x / self.grid_size).floor() as i32 + (p.z / self.grid_size).floor() as i32
             }
             Axis::ZAxis => {
-                (intersection_point.x / self.grid_size).floor() as i32
-                    + (intersection_point.y / self.grid_size).floor() as i32
+                (p.x / self.grid_size).floor() as i32 + (p.y / self.grid_size).floor() as i32
             }
         };
         let base_color = if d % 2 == 0 {
@@ -138,4 +243,37 @@ impl Primitive for Checkerboard {
             },
         ))
     }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> FloatV {
+        let axis = self.axis.index();
+        let pos = Vec3V::splat(self.pos) + self.motion.offset_packet(packet.time);
+        let ax_pos = pos[axis];
+
+        let t = (ax_pos - packet.origin[axis]) / packet.direction[axis];
+        let mut intersection_point = packet.at(t);
+        intersection_point[axis] = ax_pos;
+
+        let hit = t.cmp_ge(FloatV::ZERO)
+            & t.cmp_le(FloatV::splat(1e12))
+            & (intersection_point - pos)
+                .norm()
+                .cmp_le(FloatV::splat(self.radius));
+        hit.blend(t, FloatV::splat(Float::INFINITY))
+    }
+
+    fn error_bound(&self, point: &Vec3, _time: Float) -> Vec3 {
+        let mut error = point.abs().scale(gamma(3));
+        error[self.axis.index()] = 0.;
+        error
+    }
+
+    fn uv(&self, point: &Vec3, time: Float) -> (Float, Float) {
+        let d = *point - self.pos - self.motion.offset(time);
+        let (u, v) = match self.axis {
+            Axis::XAxis => (d.y, d.z),
+            Axis::YAxis => (d.x, d.z),
+            Axis::ZAxis => (d.x, d.y),
+        };
+        (0.5 + u / (2. * self.radius), 0.5 + v / (2. * self.radius))
+    }
 }
@@ -0,0 +1,185 @@
+use super::camera::*;
+use super::film::*;
+use super::render::*;
+use super::sampling::*;
+use super::scene::*;
+
+// PROGRESSIVE RENDERING
+// refines an image over many calls: every pass adds further samples to the
+// film, which holds the running average of all passes. The accumulation
+// starts over as soon as the scene, the camera or the render settings differ
+// from the last pass.
+pub struct ProgressiveRenderer {
+    pub film: Film,
+    pub samples_per_pass: usize,
+    pub passes: usize,
+    view: Option<(Scene, Camera, RenderSettings)>,
+}
+
+impl ProgressiveRenderer {
+    pub fn new(film: Film, samples_per_pass: usize) -> ProgressiveRenderer {
+        ProgressiveRenderer {
+            film,
+            samples_per_pass,
+            passes: 0,
+            view: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.film.clear();
+        self.passes = 0;
+        self.view = None;
+    }
+
+    // renders one more pass of samples of the given view
+    pub fn render_pass(
+        &mut self,
+        scene: &Scene,
+        camera: &Camera,
+        sampler: &dyn Sampler,
+        settings: &RenderSettings,
+    ) {
+        let unchanged = match &self.view {
+            Some((s, c, r)) => s == scene && c == camera && r == settings,
+            None => false,
+        };
+        if !unchanged {
+            self.reset();
+            self.view = Some((scene.clone(), camera.clone(), *settings));
+        }
+
+        let first_sample = self.passes * self.samples_per_pass;
+        render_samples(
+            scene,
+            camera,
+            sampler,
+            first_sample..first_sample + self.samples_per_pass,
+            settings,
+            &mut self.film,
+        );
+        self.passes += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::*;
+    use crate::media::*;
+    use crate::structs::*;
+
+    const WIDTH: usize = 8;
+    const HEIGHT: usize = 6;
+
+    fn camera(fov: Float) -> Camera {
+        Camera::create(
+            VEC3_ZERO,
+            Vec3 {
+                x: 1.,
+                y: 0.,
+                z: 0.,
+            },
+            Vec3 {
+                x: 0.,
+                y: 1.,
+                z: 0.,
+            },
+            fov,
+            WIDTH,
+            HEIGHT,
+        )
+    }
+
+    // a sphere in fog, which is sampled stochastically along every ray
+    fn scene() -> Scene {
+        Scene {
+            lights: vec![LIGHT1],
+            spheres: vec![SPHERE1],
+            boards: vec![],
+            fog: Some(Medium::fog(0.2, COLOR_WHITE, 0.)),
+        }
+    }
+
+    fn film() -> Film {
+        Film::new(WIDTH, HEIGHT, 0, WIDTH, 0, HEIGHT, Filter::Gaussian)
+    }
+
+    fn assert_same_image(a: &Film, b: &Film) {
+        for (a, b) in a.resolve().iter().zip(b.resolve()) {
+            let difference = (a.r - b.r).abs() + (a.g - b.g).abs() + (a.b - b.b).abs();
+            assert!(difference < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    // a single render with all samples of the given number of passes
+    fn rendered(scene: &Scene, camera: &Camera, passes: usize, samples_per_pass: usize) -> Film {
+        let mut film = film();
+        render_film(
+            scene,
+            camera,
+            &SobolSampler,
+            passes * samples_per_pass,
+            &DEFAULT_RENDER_SETTINGS,
+            &mut film,
+        );
+        film
+    }
+
+    #[test]
+    fn passes_accumulate_like_one_render() {
+        let (scene, camera) = (scene(), camera(1.4));
+        let mut renderer = ProgressiveRenderer::new(film(), 3);
+        for passes in 1..=4 {
+            renderer.render_pass(&scene, &camera, &SobolSampler, &DEFAULT_RENDER_SETTINGS);
+            assert_eq!(renderer.passes, passes);
+            assert_same_image(&renderer.film, &rendered(&scene, &camera, passes, 3));
+        }
+        renderer.reset();
+        assert_eq!(renderer.passes, 0);
+        assert!(renderer.film.resolve().iter().all(|&c| c == COLOR_ZERO));
+    }
+
+    // the accumulation starts over with a single pass of the new view
+    #[test]
+    fn changes_reset_the_film() {
+        let zoomed = camera(1.);
+        let (scene, camera) = (scene(), camera(1.4));
+        let settings = DEFAULT_RENDER_SETTINGS;
+        let mut moved = scene.clone();
+        moved.spheres[0].center.x += 0.5;
+        let narrower = RenderSettings {
+            max_reflection_depth: 1,
+            ..settings
+        };
+
+        for (changed_scene, changed_camera, changed_settings) in [
+            (&moved, &camera, &settings),
+            (&scene, &zoomed, &settings),
+            (&scene, &camera, &narrower),
+        ] {
+            let mut renderer = ProgressiveRenderer::new(film(), 2);
+            for _ in 0..3 {
+                renderer.render_pass(&scene, &camera, &SobolSampler, &settings);
+            }
+            renderer.render_pass(
+                changed_scene,
+                changed_camera,
+                &SobolSampler,
+                changed_settings,
+            );
+            assert_eq!(renderer.passes, 1);
+
+            let mut expected = film();
+            render_film(
+                changed_scene,
+                changed_camera,
+                &SobolSampler,
+                2,
+                changed_settings,
+                &mut expected,
+            );
+            assert_same_image(&renderer.film, &expected);
+        }
+    }
+}
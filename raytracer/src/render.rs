@@ -0,0 +1,88 @@
+use std::ops::Range;
+
+use super::camera::*;
+use super::film::*;
+use super::packet::*;
+use super::sampling::*;
+use super::scene::*;
+use super::structs::*;
+
+// primary ray through continuous image coordinates, pixel (x, y) is
+// centered on (x + 0.5, y + 0.5)
+pub fn image_ray(camera: &Camera, width: usize, height: usize, x: Float, y: Float) -> Ray {
+    let width_ = (width as Float) / 2.;
+    let height_ = (height as Float) / 2.;
+    camera.grid_ray((x - 0.5 - width_) / width_, (y - 0.5 - height_) / height_)
+}
+
+// the i-th sample of pixel (x, y): its position on the image and its camera
+// ray, at a time within the shutter interval
+pub fn camera_sample(
+    camera: &Camera,
+    sampler: &dyn Sampler,
+    film: &Film,
+    x: usize,
+    y: usize,
+    i: usize,
+    settings: &RenderSettings,
+) -> (Float, Float, Ray) {
+    let (dx, dy) = sampler.sample_2d(x, y, i, 0);
+    let (sample_x, sample_y) = (x as Float + dx, y as Float + dy);
+    let mut ray = image_ray(camera, film.width, film.height, sample_x, sample_y);
+    if settings.shutter > 0. {
+        ray.time = (sampler.sample_2d(x, y, i, 1).0 - 0.5) * settings.shutter;
+    }
+    (sample_x, sample_y, ray)
+}
+
+// traces samples_per_pixel samples for every pixel that contributes to the
+// film's region and splats them onto the film
+pub fn render_film(
+    scene: &Scene,
+    camera: &Camera,
+    sampler: &dyn Sampler,
+    samples_per_pixel: usize,
+    settings: &RenderSettings,
+    film: &mut Film,
+) {
+    render_samples(scene, camera, sampler, 0..samples_per_pixel, settings, film);
+}
+
+// like render_film, but traces the samples with the given indices, so
+// further samples can be added to a film later on
+pub fn render_samples(
+    scene: &Scene,
+    camera: &Camera,
+    sampler: &dyn Sampler,
+    samples: Range<usize>,
+    settings: &RenderSettings,
+    film: &mut Film,
+) {
+    let (start_x, end_x, start_y, end_y) = film.sample_bounds();
+    let mut positions = Vec::new();
+    let mut rays = Vec::new();
+    for y in start_y..end_y {
+        // the samples of a row are intersected in packets of neighbouring
+        // samples, which are coherent
+        positions.clear();
+        rays.clear();
+        for x in start_x..end_x {
+            for i in samples.clone() {
+                let (sample_x, sample_y, ray) =
+                    camera_sample(camera, sampler, film, x, y, i, settings);
+                positions.push((sample_x, sample_y));
+                rays.push(ray);
+            }
+        }
+
+        for (positions, rays) in positions.chunks(LANES).zip(rays.chunks(LANES)) {
+            let intersections = scene.intersect_packet(rays);
+            for ((&(sample_x, sample_y), ray), intersection) in
+                positions.iter().zip(rays).zip(intersections)
+            {
+                let sample = scene.shade_sample_hit(ray, intersection, settings);
+                film.add_shading_sample(sample_x, sample_y, &sample, scene);
+            }
+        }
+    }
+}
@@ -1,12 +1,386 @@
 use super::structs::*;
 
-// GRIDS
-pub const SAMPLE_GRID_1: [(Float, Float); 1] = [(0., 0.)];
-
-pub const SAMPLE_GRID_5: [(Float, Float); 5] = [
-    (0., 0.),
-    (0.33, 0.33),
-    (-0.33, 0.33),
-    (-0.33, -0.33),
-    (0.33, -0.33),
-];
+// SAMPLERS
+// Samplers produce well distributed points in [0, 1)^2 for every pixel. The
+// sequences are seeded per pixel and dimension, so renders are reproducible
+// and neighbouring pixels do not repeat the same pattern. Dimension 0 is the
+// position inside the pixel, higher dimensions are free for other uses.
+pub trait Sampler {
+    fn sample_2d(&self, x: usize, y: usize, index: usize, dimension: usize) -> (Float, Float);
+}
+
+// correlated multi-jittered sampling (Kensler 2013), stratified in both axes
+// for arbitrary sample counts. Indices past samples_per_pixel continue with
+// freshly permuted sets of the same size.
+pub struct StratifiedSampler {
+    pub samples_per_pixel: usize,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize) -> StratifiedSampler {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn sample_2d(&self, x: usize, y: usize, index: usize, dimension: usize) -> (Float, Float) {
+        let count = self.samples_per_pixel as u32;
+        let set = (index / self.samples_per_pixel) as u32;
+        let p = hash(pixel_seed(x, y, dimension) ^ hash(set));
+
+        let m = ((count as Float).sqrt() as u32).max(1);
+        let n = count.div_ceil(m);
+        let s = permute(
+            (index % self.samples_per_pixel) as u32,
+            count,
+            p.wrapping_mul(0x51633e2d),
+        );
+        let sx = permute(s % m, m, p.wrapping_mul(0x68bc21eb));
+        let sy = permute(s / m, n, p.wrapping_mul(0x02e5be93));
+        let jx = rand_float(s, p.wrapping_mul(0x967a889b));
+        let jy = rand_float(s, p.wrapping_mul(0x368cc8b7));
+
+        (
+            (sx as Float + (sy as Float + jx) / n as Float) / m as Float,
+            (s as Float + jy) / count as Float,
+        )
+    }
+}
+
+// Halton sequence in bases of consecutive primes, randomized with a
+// per-pixel Cranley-Patterson rotation
+pub struct HaltonSampler;
+
+const PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+impl Sampler for HaltonSampler {
+    fn sample_2d(&self, x: usize, y: usize, index: usize, dimension: usize) -> (Float, Float) {
+        let pair = (2 * dimension) % PRIMES.len();
+        let seed = pixel_seed(x, y, dimension);
+        let u = radical_inverse(index as u32, PRIMES[pair]) + rand_float(0, seed);
+        let v = radical_inverse(index as u32, PRIMES[pair + 1]) + rand_float(1, seed);
+        (u.fract(), v.fract())
+    }
+}
+
+// Sobol sequence with hash based Owen scrambling (Burley 2020), the sample
+// order is shuffled per pixel and dimension, which pads the two-dimensional
+// sequence to arbitrarily many dimensions
+pub struct SobolSampler;
+
+impl Sampler for SobolSampler {
+    fn sample_2d(&self, x: usize, y: usize, index: usize, dimension: usize) -> (Float, Float) {
+        let seed = pixel_seed(x, y, dimension);
+        let index = nested_uniform_scramble(index as u32, seed);
+
+        let u = nested_uniform_scramble(sobol(index, 0), hash(seed ^ 0x1f1c4f31));
+        let v = nested_uniform_scramble(sobol(index, 1), hash(seed ^ 0x6a2f8e15));
+        (to_unit_float(u), to_unit_float(v))
+    }
+}
+
+fn pixel_seed(x: usize, y: usize, dimension: usize) -> u32 {
+    hash(hash(hash(x as u32) ^ y as u32) ^ dimension as u32)
+}
+
+// integer hash (lowbias32 by Chris Wellons)
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+fn to_unit_float(x: u32) -> Float {
+    (x >> 8) as Float * (1. / (1u32 << 24) as Float)
+}
+
+// pseudo-random permutation of i in 0..l, keyed by p
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+// pseudo-random number in [0, 1) for index i, keyed by p
+fn rand_float(mut i: u32, p: u32) -> Float {
+    i ^= p;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb36534e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc4795);
+    i ^= 0xdf6e307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | p >> 18);
+    to_unit_float(i)
+}
+
+fn radical_inverse(mut i: u32, base: u32) -> Float {
+    let inv_base = 1. / base as Float;
+    let mut inv = inv_base;
+    let mut result: Float = 0.;
+    while i > 0 {
+        result += (i % base) as Float * inv;
+        i /= base;
+        inv *= inv_base;
+    }
+    result.min(1. - Float::EPSILON)
+}
+
+// first two dimensions of the Sobol sequence as 32 bit fixed point numbers
+fn sobol(index: u32, dimension: usize) -> u32 {
+    let mut result = 0;
+    let mut v: u32 = 1 << 31;
+    let mut i = index;
+    while i > 0 {
+        if i & 1 == 1 {
+            result ^= v;
+        }
+        v = if dimension == 0 { v >> 1 } else { v ^ (v >> 1) };
+        i >>= 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// RANDOM NUMBERS
+// small PCG32 generator, reproducible for a given seed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        let mut rng = Rng { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    // uniformly distributed in [0, 1)
+    pub fn next_float(&mut self) -> Float {
+        (self.next_u32() >> 8) as Float * (1. / (1u32 << 24) as Float)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samplers() -> [Box<dyn Sampler>; 3] {
+        [
+            Box::new(StratifiedSampler::new(16)),
+            Box::new(HaltonSampler),
+            Box::new(SobolSampler),
+        ]
+    }
+
+    #[test]
+    fn samples_are_in_unit_square() {
+        for sampler in samplers() {
+            for (x, y) in [(0, 0), (3, 7), (1919, 1079)] {
+                for dimension in 0..20 {
+                    for index in 0..64 {
+                        let (u, v) = sampler.sample_2d(x, y, index, dimension);
+                        assert!((0. ..1.).contains(&u) && (0. ..1.).contains(&v));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_reproducible() {
+        for sampler in samplers() {
+            for index in 0..32 {
+                assert_eq!(
+                    sampler.sample_2d(5, 9, index, 2),
+                    sampler.sample_2d(5, 9, index, 2)
+                );
+            }
+        }
+        // a new sampler gives the same samples
+        assert_eq!(
+            StratifiedSampler::new(16).sample_2d(5, 9, 3, 1),
+            StratifiedSampler::new(16).sample_2d(5, 9, 3, 1)
+        );
+    }
+
+    // one sample in every row and every column of the m x n grid, and one
+    // in every cell of the finer grid along each axis
+    fn assert_stratified(samples_per_pixel: usize, set: usize) {
+        let sampler = StratifiedSampler::new(samples_per_pixel);
+        let m = (samples_per_pixel as Float).sqrt() as usize;
+        let n = samples_per_pixel.div_ceil(m);
+        let samples: Vec<(Float, Float)> = (0..samples_per_pixel)
+            .map(|i| sampler.sample_2d(4, 2, set * samples_per_pixel + i, 0))
+            .collect();
+
+        let mut fine_x = vec![0; m * n];
+        let mut fine_y = vec![0; samples_per_pixel];
+        let mut cells = vec![0; m * n];
+        for (u, v) in samples {
+            fine_x[(u * (m * n) as Float) as usize] += 1;
+            fine_y[(v * samples_per_pixel as Float) as usize] += 1;
+            cells[(v * n as Float) as usize * m + (u * m as Float) as usize] += 1;
+        }
+        assert!(fine_x.iter().all(|&c| c <= 1));
+        assert!(fine_y.iter().all(|&c| c == 1));
+        assert!(cells.iter().all(|&c| c <= 1));
+    }
+
+    #[test]
+    fn stratified_sampler_fills_every_stratum() {
+        for samples_per_pixel in [1, 4, 9, 16, 64] {
+            assert_stratified(samples_per_pixel, 0);
+            // also the sets past samples_per_pixel
+            assert_stratified(samples_per_pixel, 3);
+        }
+        // counts that are not square leave cells of the last row empty
+        assert_stratified(6, 0);
+        assert_stratified(10, 1);
+    }
+
+    // exactly one sample in every cell of the columns x rows grid
+    fn assert_one_per_cell(samples: &[(Float, Float)], columns: usize, rows: usize) {
+        let mut cells = vec![0; columns * rows];
+        for &(u, v) in samples {
+            cells[(v * rows as Float) as usize * columns + (u * columns as Float) as usize] += 1;
+        }
+        assert!(
+            cells.iter().all(|&c| c == 1),
+            "{} x {} cells: {:?}",
+            columns,
+            rows,
+            cells
+        );
+    }
+
+    // every block of 2^k consecutive samples, starting at a multiple of 2^k,
+    // has one sample in each elementary interval of area 2^-k, for all
+    // pixels and dimensions despite the scrambling and shuffling
+    #[test]
+    fn sobol_sampler_fills_every_elementary_interval() {
+        for (x, y, dimension) in [(0, 0, 0), (4, 2, 0), (17, 5, 3)] {
+            for k in 0..=8 {
+                for block in 0..3 {
+                    let samples: Vec<(Float, Float)> = (block << k..(block + 1) << k)
+                        .map(|index| SobolSampler.sample_2d(x, y, index, dimension))
+                        .collect();
+                    for a in 0..=k {
+                        assert_one_per_cell(&samples, 1 << a, 1 << (k - a));
+                    }
+                }
+            }
+        }
+    }
+
+    // the first 2^a 3^b samples have one sample in every cell of the
+    // 2^a x 3^b grid. The rotation moves the grid along, so the cells are
+    // taken relative to it, with a margin for rounding at the cell borders.
+    #[test]
+    fn halton_sampler_fills_every_cell() {
+        for (x, y) in [(0, 0), (4, 2), (17, 5)] {
+            let seed = pixel_seed(x, y, 0);
+            let (du, dv) = (rand_float(0, seed), rand_float(1, seed));
+            for (a, b) in [(0, 0), (1, 0), (0, 1), (2, 1), (1, 2), (3, 2), (4, 1)] {
+                let (columns, rows) = (1 << a, 3_usize.pow(b));
+                let samples: Vec<(Float, Float)> = (0..columns * rows)
+                    .map(|index| {
+                        let (u, v) = HaltonSampler.sample_2d(x, y, index, 0);
+                        let unrotate = |w: Float, d: Float| (w - d + 1e-4).rem_euclid(1.);
+                        (unrotate(u, du), unrotate(v, dv))
+                    })
+                    .collect();
+                assert_one_per_cell(&samples, columns, rows);
+            }
+        }
+    }
+
+    #[test]
+    fn dimensions_are_decorrelated() {
+        let samplers: [Box<dyn Sampler>; 2] = [Box::new(HaltonSampler), Box::new(SobolSampler)];
+        for sampler in samplers {
+            for dimension in 1..20 {
+                let differ = (0..16).any(|index| {
+                    sampler.sample_2d(2, 3, index, 0) != sampler.sample_2d(2, 3, index, dimension)
+                });
+                assert!(differ, "dimension {} repeats dimension 0", dimension);
+            }
+        }
+    }
+
+    #[test]
+    fn pixels_are_decorrelated() {
+        for sampler in samplers() {
+            assert_ne!(sampler.sample_2d(0, 0, 0, 0), sampler.sample_2d(1, 0, 0, 0));
+            assert_ne!(sampler.sample_2d(0, 0, 0, 0), sampler.sample_2d(0, 1, 0, 0));
+        }
+    }
+
+    #[test]
+    fn rng_floats_are_in_unit_interval() {
+        let mut rng = Rng::new(7);
+        assert!((0..10000)
+            .map(|_| rng.next_float())
+            .all(|u| (0. ..1.).contains(&u)));
+        assert_eq!(Rng::new(7).next_u32(), Rng::new(7).next_u32());
+    }
+}
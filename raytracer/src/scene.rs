@@ -1,7 +1,14 @@
+use super::animation::*;
 use super::color::*;
+use super::media::*;
+use super::packet::*;
 use super::primitives::*;
+use super::sampling::*;
 use super::structs::*;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LightSource {
     pub pos: Vec3,
     pub color: Color,
@@ -22,11 +29,11 @@ impl LightSource {
     ) -> Float {
         let (c, i, n) = (*camera_pos, *intersection_point, *normal);
 
-        let V: Vec3 = (c - i).normalize();
-        let L: Vec3 = (self.pos - i).normalize();
-        let R: Vec3 = n.scale(2. * n.dot(L)) - L;
+        let v: Vec3 = (c - i).normalize();
+        let l: Vec3 = (self.pos - i).normalize();
+        let r: Vec3 = n.scale(2. * n.dot(l)) - l;
 
-        let c = R.dot(V).max(0.);
+        let c = r.dot(v).max(0.);
 
         c.powf(specular_coefficient)
     }
@@ -40,61 +47,288 @@ pub const OPTIONS: ShadingOptions = ShadingOptions {
     specular_coefficient: 4.,
     reflective_part: 0.,
     refraction_index: 0.,
+    interior: None,
 };
 
+// fields left out in scene files are taken from OPTIONS
+impl Default for ShadingOptions {
+    fn default() -> ShadingOptions {
+        OPTIONS
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Scene {
     pub lights: Vec<LightSource>,
+    #[serde(default)]
     pub spheres: Vec<Sphere>,
+    #[serde(default)]
     pub boards: Vec<Checkerboard>,
+    // homogeneous medium filling the space outside of all volumes
+    #[serde(default)]
+    pub fog: Option<Medium>,
+}
+
+// first surface hit by a shaded ray
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SurfaceHit {
+    pub object_id: usize,
+    pub t: Float,
+    // distance from the ray origin
+    pub distance: Float,
+    // time of the ray
+    pub time: Float,
+    pub point: Vec3,
+    // normal facing the ray
+    pub normal: Vec3,
+    pub options: ShadingOptions,
+}
+
+// color of a shaded ray, split into light arriving directly from the light
+// sources at the first hit and everything else (ambient, reflected and
+// transmitted light)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadingSample {
+    pub color: Color,
+    pub direct: Color,
+    pub indirect: Color,
+    pub hit: Option<SurfaceHit>,
+}
+
+// limits on the tree of rays spawned by a camera ray. Secondary rays whose
+// contribution to the pixel is below min_contribution are not traced, below
+// throughput_cutoff they are traced with russian roulette, i.e. with a
+// probability proportional to their contribution, which keeps the result
+// unbiased while pruning most of the rays that barely contribute.
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub max_reflection_depth: u32,
+    // passing into or out of a volume counts as one refraction
+    pub max_refraction_depth: u32,
+    pub min_contribution: Float,
+    pub throughput_cutoff: Float,
+    // seconds the shutter is open, centered on the time of the scene, camera
+    // rays are spread over this interval to blur moving objects
+    pub shutter: Float,
+}
+
+pub const DEFAULT_RENDER_SETTINGS: RenderSettings = RenderSettings {
+    max_reflection_depth: 4,
+    max_refraction_depth: 8,
+    min_contribution: 1e-3,
+    throughput_cutoff: 0.01,
+    shutter: 0.,
+};
+
+// state of a path through the tree of rays: how many reflections and
+// refractions led to the current ray, and the fraction of its color that
+// ends up in the pixel
+#[derive(Debug, Copy, Clone)]
+struct PathState {
+    reflections: u32,
+    refractions: u32,
+    throughput: Float,
+}
+
+// closest intersection of a ray and the id of the object hit
+pub type ObjectIntersection = Option<(usize, (Float, Vec3, Vec3, ShadingOptions))>;
+
+// upper bound on the volume boundaries a shadow ray is traced through
+const MAX_CROSSINGS: usize = 64;
+
+// origin for rays leaving a surface hit at `point` in `direction`, moved along
+// the surface normal past the rounding error of the point, so the rays cannot
+// hit the surface again right where they start. Scales with the magnitude of
+// the point instead of using a fixed epsilon, see Physically Based Rendering,
+// section 6.8.
+fn offset_ray_origin(point: Vec3, error: Vec3, normal: Vec3, direction: Vec3) -> Vec3 {
+    let mut offset = normal.scale(normal.abs().dot(error));
+    if direction.dot(normal) < 0. {
+        offset = -offset;
+    }
+    let mut origin = point + offset;
+    // the addition may have rounded towards the point
+    for axis in 0..3 {
+        if offset[axis] > 0. {
+            origin[axis] = origin[axis].next_up();
+        } else if offset[axis] < 0. {
+            origin[axis] = origin[axis].next_down();
+        }
+    }
+    origin
 }
 
-const BIAS: Float = 1e-5;
+fn spawn_ray(point: Vec3, error: Vec3, normal: Vec3, direction: Vec3, time: Float) -> Ray {
+    Ray {
+        origin: offset_ray_origin(point, error, normal, direction),
+        direction: direction.normalize(),
+        time,
+    }
+}
+
+// seeds the random numbers used along a ray from the ray itself, so renders
+// are reproducible without passing a generator through the public API
+#[allow(clippy::unnecessary_cast)] // to_bits is u32 or u64 depending on Float
+fn ray_seed(ray: &Ray) -> u64 {
+    let (o, d) = (ray.origin, ray.direction);
+    [o.x, o.y, o.z, d.x, d.y, d.z]
+        .iter()
+        .fold(0xcbf29ce484222325, |h: u64, v| {
+            (h ^ v.to_bits() as u64).wrapping_mul(0x100000001b3)
+        })
+}
 
 impl Scene {
+    // all primitives of the scene, their position in this sequence is the
+    // object id used in AOVs (spheres first, then checkerboards)
+    pub fn objects(&self) -> impl Iterator<Item = &dyn Primitive> {
+        self.spheres
+            .iter()
+            .map(|sphere| sphere as &dyn Primitive)
+            .chain(self.boards.iter().map(|board| board as &dyn Primitive))
+    }
+
+    // id of the first object declaring the same shading options
+    pub fn material_id(&self, object_id: usize) -> usize {
+        let options = |id: usize| match self.spheres.get(id) {
+            Some(sphere) => &sphere.options,
+            None => &self.boards[id - self.spheres.len()].options,
+        };
+        (0..object_id)
+            .find(|&id| options(id) == options(object_id))
+            .unwrap_or(object_id)
+    }
+
+    // surface coordinates of a point on an object at the given time
+    pub fn uv(&self, object_id: usize, point: &Vec3, time: Float) -> (Float, Float) {
+        self.objects()
+            .nth(object_id)
+            .map_or((0., 0.), |object| object.uv(point, time))
+    }
+
+    // rounding error of an intersection point on an object at the given time
+    pub fn error_bound(&self, object_id: usize, point: &Vec3, time: Float) -> Vec3 {
+        self.objects()
+            .nth(object_id)
+            .map_or(VEC3_ZERO, |object| object.error_bound(point, time))
+    }
+
     pub fn intersect(&self, ray: &Ray) -> Intersection {
-        let mut intersection: Intersection = None;
+        self.intersect_object(ray)
+            .map(|(_, intersection)| intersection)
+    }
+
+    // closest intersection along the ray, together with the id of the object hit
+    pub fn intersect_object(&self, ray: &Ray) -> ObjectIntersection {
+        let mut intersection = None;
         let mut closest_t: Float = 0.;
 
-        for sphere in &self.spheres {
-            let potential_intersection = sphere.intersect(ray);
+        for (id, object) in self.objects().enumerate() {
+            let potential_intersection = object.intersect(ray);
             match potential_intersection {
                 None => continue,
                 Some((t, _, _, _)) => {
-                    if t < BIAS {
+                    if t <= 0. {
                         continue;
                     }
 
                     if intersection.is_none() || (t < closest_t) {
-                        intersection = potential_intersection;
+                        intersection = potential_intersection.map(|i| (id, i));
                         closest_t = t;
                     }
                 }
             }
         }
 
-        for board in &self.boards {
-            let potential_intersection = board.intersect(ray);
-            match potential_intersection {
-                None => continue,
-                Some((t, _, _, _)) => {
-                    if t < BIAS {
-                        continue;
-                    }
+        intersection
+    }
 
-                    if intersection.is_none() || (t < closest_t) {
-                        intersection = potential_intersection;
-                        closest_t = t;
+    // intersect_object for up to LANES rays at once, the same intersections
+    // in the same order. Only the distances are computed for all objects,
+    // the closest object of each ray then computes the rest of its hit.
+    pub fn intersect_packet(&self, rays: &[Ray]) -> [ObjectIntersection; LANES] {
+        let packet = RayPacket::new(rays);
+        let mut closest_t = FloatV::splat(Float::INFINITY);
+        let mut closest_id = FloatV::splat(-1.);
+
+        for (id, object) in self.objects().enumerate() {
+            let t = object.intersect_packet(&packet);
+            let closer = t.cmp_gt(FloatV::ZERO) & t.cmp_lt(closest_t);
+            closest_t = closer.blend(t, closest_t);
+            closest_id = closer.blend(FloatV::splat(id as Float), closest_id);
+        }
+
+        let ids = closest_id.to_array();
+        std::array::from_fn(|lane| {
+            let ray = rays.get(lane)?;
+            if ids[lane] < 0. {
+                return None;
+            }
+            let id = ids[lane] as usize;
+            let intersection = self.objects().nth(id)?.intersect(ray)?;
+            Some((id, intersection))
+        })
+    }
+
+    // fraction of the light at `to` that arrives at `from`, starting out in
+    // the given medium. Points on surfaces have to be offset beforehand.
+    // Surfaces are opaque unless they bound a volume, in which case the light
+    // is attenuated by the medium inside. Moving objects are where they are
+    // at the given time.
+    pub fn transmittance(
+        &self,
+        from: Vec3,
+        to: Vec3,
+        time: Float,
+        medium: Option<Medium>,
+    ) -> Color {
+        let mut transmittance = COLOR_WHITE;
+        let mut medium = medium;
+        let mut origin = from;
+
+        for _ in 0..MAX_CROSSINGS {
+            let d = (to - origin).norm();
+            let ray = Ray {
+                origin,
+                direction: (to - origin).scale(1. / d),
+                time,
+            };
+            let hit = match self.intersect_object(&ray) {
+                Some((id, (t, point, normal, options))) if t < d => {
+                    Some((id, t, point, normal, options))
+                }
+                _ => None,
+            };
+
+            let segment = hit.map_or(d, |(_, t, _, _, _)| t);
+            if let Some(medium) = medium {
+                transmittance *= medium.transmittance(segment);
+            }
+
+            match hit {
+                None => return transmittance,
+                Some((id, _, point, normal, options)) => {
+                    if options.interior.is_none() {
+                        return COLOR_BLACK;
                     }
+                    medium = if ray.direction.dot(normal) < 0. {
+                        options.interior
+                    } else {
+                        self.fog
+                    };
+                    let error = self.error_bound(id, &point, time);
+                    origin = offset_ray_origin(point, error, normal, ray.direction);
                 }
             }
         }
 
-        intersection
+        COLOR_BLACK
     }
 
     pub fn shade(&self, ray: &Ray, recursive: u32) -> Color {
-        let (c, _, _, _) = self.shade_with_last_intersect(ray, recursive);
-        c
+        self.shade_sample(ray, &Scene::recursive_settings(recursive))
+            .color
     }
 
     pub fn shade_with_last_intersect(
@@ -102,29 +336,152 @@ impl Scene {
         ray: &Ray,
         recursive: u32,
     ) -> (Color, Vec3, Float, ShadingOptions) {
-        // ToDo: implement refraction
+        let sample = self.shade_sample(ray, &Scene::recursive_settings(recursive));
+        match sample.hit {
+            Some(hit) => (sample.color, hit.normal, hit.t, hit.options),
+            None => (sample.color, VEC3_ZERO, 0., OPTIONS),
+        }
+    }
 
-        match self.intersect(ray) {
-            Some((t, intersection_point, normal, options)) => {
-                let mut light_intensity = 0.;
+    // the given number of reflections and no other limits, so shade traces
+    // the whole tree of rays as it did before there were render settings
+    fn recursive_settings(recursive: u32) -> RenderSettings {
+        RenderSettings {
+            max_reflection_depth: recursive,
+            max_refraction_depth: u32::MAX,
+            min_contribution: 0.,
+            throughput_cutoff: 0.,
+            ..DEFAULT_RENDER_SETTINGS
+        }
+    }
+
+    // shades a ray and keeps what is known about its first hit
+    pub fn shade_sample(&self, ray: &Ray, settings: &RenderSettings) -> ShadingSample {
+        self.shade_sample_hit(ray, self.intersect_object(ray), settings)
+    }
+
+    // shade_sample with the ray's closest intersection already known, as
+    // found by intersect_object or intersect_packet
+    pub fn shade_sample_hit(
+        &self,
+        ray: &Ray,
+        intersection: ObjectIntersection,
+        settings: &RenderSettings,
+    ) -> ShadingSample {
+        let mut rng = Rng::new(ray_seed(ray));
+        let path = PathState {
+            reflections: 0,
+            refractions: 0,
+            throughput: 1.,
+        };
+        self.shade_in_medium(ray, intersection, settings, path, self.fog, &mut rng)
+    }
+
+    // color of a secondary ray contributing the given fraction of the color
+    // at the current hit, None if the ray is not traced
+    fn shade_secondary(
+        &self,
+        ray: &Ray,
+        settings: &RenderSettings,
+        path: PathState,
+        fraction: Float,
+        medium: Option<Medium>,
+        rng: &mut Rng,
+    ) -> Option<Color> {
+        let throughput = path.throughput * fraction;
+        if throughput <= 0. || throughput < settings.min_contribution {
+            return None;
+        }
+        let mut path = PathState { throughput, ..path };
+        let mut compensation = 1.;
+        if throughput < settings.throughput_cutoff {
+            let survival = throughput / settings.throughput_cutoff;
+            if rng.next_float() >= survival {
+                return None;
+            }
+            compensation = 1. / survival;
+            path.throughput = settings.throughput_cutoff;
+        }
+        let intersection = self.intersect_object(ray);
+        let sample = self.shade_in_medium(ray, intersection, settings, path, medium, rng);
+        Some(sample.color.scale(compensation))
+    }
+
+    fn shade_in_medium(
+        &self,
+        ray: &Ray,
+        intersection: ObjectIntersection,
+        settings: &RenderSettings,
+        path: PathState,
+        medium: Option<Medium>,
+        rng: &mut Rng,
+    ) -> ShadingSample {
+        let hit = intersection.map(|(object_id, (t, point, normal, options))| {
+            // volume boundaries can be hit from the inside, shade them
+            // with the normal facing the incoming ray
+            let normal = Vec3::from(Normal3::new(normal).face_forward(-ray.direction));
+            SurfaceHit {
+                object_id,
+                t,
+                distance: t * ray.direction.norm(),
+                time: ray.time,
+                point,
+                normal,
+                options,
+            }
+        });
+
+        // free-flight sampling: the ray either scatters in the medium before
+        // reaching the next surface or is attenuated on its way there
+        let mut weight = COLOR_WHITE;
+        if let Some(medium) = medium {
+            let speed = ray.direction.norm();
+            let t_max = hit.map_or(Float::INFINITY, |hit| hit.distance);
+            match medium.sample_distance(t_max, rng) {
+                FreeFlight::Scattered(distance, scattered_weight) => {
+                    let point = ray.at(distance / speed);
+                    let c = self.in_scattered(&point, &ray.direction, ray.time, &medium)
+                        * scattered_weight;
+                    return ShadingSample {
+                        color: c,
+                        direct: c,
+                        indirect: COLOR_ZERO,
+                        hit,
+                    };
+                }
+                FreeFlight::Passed(passed_weight) => weight = passed_weight,
+            }
+        }
+
+        match (intersection, hit) {
+            (Some((object_id, (_, intersection_point, normal, options))), Some(hit)) => {
+                let entering = ray.direction.dot(normal) < 0.;
+                let error = self.error_bound(object_id, &intersection_point, ray.time);
+                let normal = hit.normal;
+
+                let mut light_intensity = COLOR_ZERO;
                 let mut specular_color: Color = COLOR_ZERO;
                 for light in &self.lights {
                     // skip light if it is not visible
-                    let d = (light.pos - intersection_point).norm();
-                    let light_ray: Ray = Ray {
-                        origin: intersection_point,
-                        direction: (light.pos - intersection_point).scale(1. / d),
+                    let to_light = light.pos - intersection_point;
+                    let origin = offset_ray_origin(intersection_point, error, normal, to_light);
+                    // lights behind a volume boundary are seen through the
+                    // medium on its other side, like refracted rays
+                    let light_medium = if to_light.dot(normal) >= 0. || options.interior.is_none() {
+                        medium
+                    } else if entering {
+                        options.interior
+                    } else {
+                        self.fog
                     };
-                    match self.intersect(&light_ray) {
-                        None => (),
-                        Some((t, _, _, _)) => {
-                            if t + BIAS < d {
-                                continue;
-                            }
-                        }
+                    let transmittance =
+                        self.transmittance(origin, light.pos, ray.time, light_medium);
+                    if transmittance.r + transmittance.g + transmittance.b == 0. {
+                        continue;
                     }
 
-                    light_intensity += light.diffuse(&intersection_point, &normal);
+                    light_intensity +=
+                        transmittance.scale(light.diffuse(&intersection_point, &normal));
 
                     let specular_intensity = light.specular(
                         &intersection_point,
@@ -132,41 +489,110 @@ impl Scene {
                         &ray.origin,
                         options.specular_coefficient,
                     );
-                    specular_color += light.color.scale(specular_intensity);
+                    specular_color += light.color * transmittance * specular_intensity;
                 }
-                light_intensity = light_intensity.min(1.);
-                let diffuse_color = options
-                    .base_color
-                    .scale(options.diffuse_part * light_intensity + options.ambiant_part);
+                let base_color = options.base_color;
+                let diffuse_color =
+                    base_color * options.diffuse_part * light_intensity.map(|c| c.min(1.));
+                let ambiant_color = base_color.scale(options.ambiant_part);
 
                 // ToDo: physically correct combination of colors
                 specular_color =
                     specular_color.scale(options.specular_part / (self.lights.len() as Float));
 
-                let reflected_color = if options.reflective_part > 1e-5 && recursive > 0 {
-                    let c = -ray.direction.normalize();
-                    let direction = normal.scale(2. * normal.dot(c)) - c;
-                    let reflected_ray = Ray {
-                        origin: intersection_point,
-                        direction,
+                // fraction of the secondary rays' colors reaching the pixel
+                let passed = weight.r.max(weight.g).max(weight.b);
+
+                let reflected_color = if path.reflections < settings.max_reflection_depth {
+                    let direction = ray.direction.normalize().reflect(normal);
+                    let reflected_ray =
+                        spawn_ray(intersection_point, error, normal, direction, ray.time);
+                    let path = PathState {
+                        reflections: path.reflections + 1,
+                        ..path
                     };
 
-                    self.shade(&reflected_ray, recursive - 1)
+                    self.shade_secondary(
+                        &reflected_ray,
+                        settings,
+                        path,
+                        options.reflective_part * passed,
+                        medium,
+                        rng,
+                    )
+                    .unwrap_or(COLOR_ZERO)
                 } else {
                     COLOR_ZERO
                 }
                 .scale(options.reflective_part);
 
-                (
-                    diffuse_color + specular_color + reflected_color,
-                    normal,
-                    t,
-                    options,
-                )
+                let transmitted_color = if options.interior.is_some()
+                    && path.refractions < settings.max_refraction_depth
+                {
+                    let next_medium = if entering { options.interior } else { self.fog };
+                    let n = options.refraction_index;
+                    let eta = if n == 0. {
+                        1.
+                    } else if entering {
+                        1. / n
+                    } else {
+                        n
+                    };
+
+                    match ray.direction.refract(normal, eta) {
+                        Some(direction) => {
+                            let transmitted_ray =
+                                spawn_ray(intersection_point, error, normal, direction, ray.time);
+                            let path = PathState {
+                                refractions: path.refractions + 1,
+                                ..path
+                            };
+                            self.shade_secondary(
+                                &transmitted_ray,
+                                settings,
+                                path,
+                                (1. - options.reflective_part) * passed,
+                                next_medium,
+                                rng,
+                            )
+                            .unwrap_or(COLOR_ZERO)
+                        }
+                        None => COLOR_ZERO,
+                    }
+                    .scale(1. - options.reflective_part)
+                } else {
+                    COLOR_ZERO
+                };
+
+                let direct = (diffuse_color + specular_color) * weight;
+                let indirect = (ambiant_color + reflected_color + transmitted_color) * weight;
+                ShadingSample {
+                    color: direct + indirect,
+                    direct,
+                    indirect,
+                    hit: Some(hit),
+                }
             }
-            None => (COLOR_ZERO, VEC3_ZERO, 0., OPTIONS),
+            _ => ShadingSample {
+                color: COLOR_ZERO,
+                direct: COLOR_ZERO,
+                indirect: COLOR_ZERO,
+                hit: None,
+            },
         }
     }
+
+    // light scattered towards the viewer at a point inside a medium
+    fn in_scattered(&self, point: &Vec3, direction: &Vec3, time: Float, medium: &Medium) -> Color {
+        let direction = direction.normalize();
+        let mut c = COLOR_ZERO;
+        for light in &self.lights {
+            let transmittance = self.transmittance(*point, light.pos, time, Some(*medium));
+            let cos_theta = direction.dot((light.pos - *point).normalize());
+            c += light.color * transmittance * medium.phase.eval(cos_theta);
+        }
+        c
+    }
 }
 
 pub const WHITE_OPTIONS: ShadingOptions = ShadingOptions {
@@ -177,6 +603,7 @@ pub const WHITE_OPTIONS: ShadingOptions = ShadingOptions {
     specular_coefficient: 4.,
     reflective_part: 0.,
     refraction_index: 0.,
+    interior: None,
 };
 
 pub const SPHERE1: Sphere = Sphere {
@@ -187,6 +614,7 @@ pub const SPHERE1: Sphere = Sphere {
     },
     radius: 1.,
     options: OPTIONS,
+    motion: NO_MOTION,
 };
 pub const SPHERE2: Sphere = Sphere {
     center: Vec3 {
@@ -196,6 +624,7 @@ pub const SPHERE2: Sphere = Sphere {
     },
     radius: 0.5,
     options: OPTIONS,
+    motion: NO_MOTION,
 };
 pub const SPHERE3: Sphere = Sphere {
     center: Vec3 {
@@ -205,6 +634,7 @@ pub const SPHERE3: Sphere = Sphere {
     },
     radius: 0.5,
     options: OPTIONS,
+    motion: NO_MOTION,
 };
 
 pub const Z_CHECKERBOARD1: Checkerboard = Checkerboard {
@@ -217,6 +647,7 @@ pub const Z_CHECKERBOARD1: Checkerboard = Checkerboard {
     radius: 5.,
     grid_size: 1.,
     options: WHITE_OPTIONS,
+    motion: NO_MOTION,
 };
 pub const Z_CHECKERBOARD2: Checkerboard = Checkerboard {
     axis: Axis::ZAxis,
@@ -228,6 +659,7 @@ pub const Z_CHECKERBOARD2: Checkerboard = Checkerboard {
     radius: 5.,
     grid_size: 1.,
     options: WHITE_OPTIONS,
+    motion: NO_MOTION,
 };
 
 pub const Y_CHECKERBOARD: Checkerboard = Checkerboard {
@@ -240,6 +672,7 @@ pub const Y_CHECKERBOARD: Checkerboard = Checkerboard {
     radius: 5.,
     grid_size: 1.,
     options: WHITE_OPTIONS,
+    motion: NO_MOTION,
 };
 
 pub const LIGHT1: LightSource = LightSource {
@@ -268,3 +701,234 @@ pub const LIGHT2: LightSource = LightSource {
         a: 1.0,
     },
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vec3(x: Float, y: Float, z: Float) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn path() -> PathState {
+        PathState {
+            reflections: 0,
+            refractions: 0,
+            throughput: 1.,
+        }
+    }
+
+    // a unit sphere at the origin bounding the given medium, surrounded by
+    // fog, lit by a single light
+    fn volume_scene(interior: Medium, fog: Medium, light: Vec3) -> Scene {
+        Scene {
+            lights: vec![LightSource {
+                pos: light,
+                color: COLOR_WHITE,
+            }],
+            spheres: vec![Sphere {
+                center: VEC3_ZERO,
+                radius: 1.,
+                options: ShadingOptions {
+                    specular_coefficient: 1.,
+                    interior: Some(interior),
+                    ..WHITE_OPTIONS
+                },
+                motion: NO_MOTION,
+            }],
+            boards: vec![],
+            fog: Some(fog),
+        }
+    }
+
+    // direct light at the first hit of a ray travelling through the given
+    // medium
+    fn direct(scene: &Scene, ray: &Ray, medium: Medium) -> Color {
+        let mut rng = Rng::new(1);
+        let intersection = scene.intersect_object(ray);
+        let settings = Scene::recursive_settings(0);
+        scene
+            .shade_in_medium(ray, intersection, &settings, path(), Some(medium), &mut rng)
+            .direct
+    }
+
+    fn clear() -> Medium {
+        Medium::fog(0., COLOR_WHITE, 0.)
+    }
+
+    fn absorbing() -> Medium {
+        Medium::fog(2., COLOR_BLACK, 0.)
+    }
+
+    // a light inside the volume a ray enters is seen through the interior
+    #[test]
+    fn lights_inside_a_volume_are_attenuated_by_its_interior() {
+        let light = vec3(0.9, 0.3, 0.);
+        let ray = Ray {
+            origin: vec3(-3., 3., 0.),
+            direction: vec3(3., -2., 0.),
+            time: 0.,
+        };
+        let unattenuated = direct(&volume_scene(clear(), clear(), light), &ray, clear());
+        let attenuated = direct(&volume_scene(absorbing(), clear(), light), &ray, clear());
+        // the ray hits the top of the sphere
+        let distance = (light - vec3(0., 1., 0.)).norm();
+        assert!(unattenuated.b > 0.05);
+        let expected = absorbing().transmittance(distance).b;
+        assert!((attenuated.b / unattenuated.b - expected).abs() < 1e-3);
+    }
+
+    // a light outside the volume a ray leaves is seen through the fog
+    #[test]
+    fn lights_outside_a_volume_are_attenuated_by_the_fog() {
+        let light = vec3(2., 1.2, 0.);
+        let ray = Ray {
+            origin: vec3(-0.5, 0., 0.),
+            direction: vec3(0.5, 1., 0.),
+            time: 0.,
+        };
+        let unattenuated = direct(&volume_scene(clear(), clear(), light), &ray, clear());
+        let attenuated = direct(&volume_scene(clear(), absorbing(), light), &ray, clear());
+        let distance = (light - vec3(0., 1., 0.)).norm();
+        assert!(unattenuated.b > 0.05);
+        let expected = absorbing().transmittance(distance).b;
+        assert!((attenuated.b / unattenuated.b - expected).abs() < 1e-3);
+    }
+
+    // two mirroring spheres facing each other with the camera between them,
+    // the ray bounces back and forth between them
+    fn mirrors() -> Scene {
+        let sphere = |z: Float, base_color: Color| Sphere {
+            center: vec3(0., 0., z),
+            radius: 1.,
+            options: ShadingOptions {
+                base_color,
+                reflective_part: 0.5,
+                ..OPTIONS
+            },
+            motion: NO_MOTION,
+        };
+        Scene {
+            lights: vec![LIGHT1],
+            spheres: vec![sphere(5., COLOR_BLUE), sphere(-5., COLOR_RED)],
+            boards: vec![],
+            fog: None,
+        }
+    }
+
+    const FORWARD: Ray = Ray {
+        origin: VEC3_ZERO,
+        direction: Vec3 {
+            x: 0.,
+            y: 0.,
+            z: 1.,
+        },
+        time: 0.,
+    };
+
+    // settings limiting nothing but the given depths
+    fn depths(max_reflection_depth: u32, max_refraction_depth: u32) -> RenderSettings {
+        RenderSettings {
+            max_reflection_depth,
+            max_refraction_depth,
+            min_contribution: 0.,
+            throughput_cutoff: 0.,
+            ..DEFAULT_RENDER_SETTINGS
+        }
+    }
+
+    #[test]
+    fn reflection_depth_truncates_paths() {
+        let scene = mirrors();
+        let color = |depth| scene.shade_sample(&FORWARD, &depths(depth, 0)).color;
+        // the red sphere is only seen in the mirror
+        assert!(color(1).r > color(0).r + 0.1);
+        // every further bounce adds half as much as the one before
+        for depth in 1..6 {
+            let (previous, next) = (
+                color(depth) - color(depth - 1),
+                color(depth + 1) - color(depth),
+            );
+            let added = |c: Color| c.r + c.g + c.b;
+            assert!(added(next) > 0.);
+            assert!((added(next) / added(previous) - 0.5).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn refraction_depth_truncates_paths() {
+        // a glass sphere in front of the blue one
+        let mut scene = mirrors();
+        scene.spheres[1] = Sphere {
+            center: vec3(0., 0., 2.),
+            radius: 0.5,
+            options: ShadingOptions {
+                base_color: COLOR_RED,
+                reflective_part: 0.,
+                refraction_index: 1.5,
+                interior: Some(Medium::fog(0., COLOR_WHITE, 0.)),
+                ..OPTIONS
+            },
+            motion: NO_MOTION,
+        };
+        let color = |depth| scene.shade_sample(&FORWARD, &depths(0, depth)).color;
+        // entering the glass shows its inside, leaving it the sphere behind
+        assert!(color(1) != color(0));
+        assert!(color(2).b > color(1).b);
+        assert_eq!(color(2), color(3));
+    }
+
+    // bounces contributing less than min_contribution are not traced
+    #[test]
+    fn min_contribution_skips_bounces() {
+        let scene = mirrors();
+        let limited = RenderSettings {
+            min_contribution: 0.3,
+            ..depths(10, 0)
+        };
+        // only the first reflection contributes more than 0.3
+        assert_eq!(
+            scene.shade_sample(&FORWARD, &limited).color,
+            scene.shade_sample(&FORWARD, &depths(1, 0)).color
+        );
+    }
+
+    // russian roulette terminates most bounces, but weights the surviving
+    // ones so that the average color stays the same
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let scene = mirrors();
+        let settings = depths(6, 0);
+        let shade = |settings: &RenderSettings, seed: u64| {
+            let mut rng = Rng::new(seed);
+            let intersection = scene.intersect_object(&FORWARD);
+            scene
+                .shade_in_medium(&FORWARD, intersection, settings, path(), None, &mut rng)
+                .color
+        };
+        let expected = shade(&settings, 0);
+
+        let roulette = RenderSettings {
+            throughput_cutoff: 0.9,
+            ..settings
+        };
+        let n = 20_000;
+        let mut sum = COLOR_ZERO;
+        let mut terminated = false;
+        for seed in 0..n {
+            let color = shade(&roulette, seed);
+            terminated |= color != expected;
+            sum += color;
+        }
+        assert!(terminated);
+        let mean = sum.scale(1. / n as Float);
+        for (mean, expected) in [(mean.r, expected.r), (mean.b, expected.b)] {
+            assert!(
+                (mean - expected).abs() < 0.01 * expected,
+                "{} {}",
+                mean,
+                expected
+            );
+        }
+    }
+}
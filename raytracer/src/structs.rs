@@ -1,12 +1,30 @@
+use serde::{Deserialize, Serialize};
 use std::ops;
 use wasm_bindgen::prelude::*;
 
+// precision of all computations, the f64 feature trades speed and memory for
+// accuracy in large scenes
+#[cfg(not(feature = "f64"))]
 pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+pub const PI: Float = std::f64::consts::PI as Float;
+
+// bound on the relative rounding error accumulated over n floating point
+// operations, (1 ± ε)^n ≤ 1 + gamma(n)
+pub fn gamma(n: i32) -> Float {
+    let e = Float::EPSILON * 0.5;
+    n as Float * e / (1. - n as Float * e)
+}
 
 #[wasm_bindgen]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // time in seconds relative to the time the scene was built for, moving
+    // objects are intersected where they are at this time
+    pub time: Float,
 }
 
 impl Ray {
@@ -16,7 +34,7 @@ impl Ray {
 }
 
 #[wasm_bindgen]
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Vec3 {
     pub x: Float,
     pub y: Float,
@@ -77,13 +95,11 @@ impl Vec3 {
         };
 
         // Rotate around z-axis
-        let self_xyz = Vec3 {
+        Vec3 {
             x: self_xy.x * z_rot.cos() - self_xy.y * z_rot.sin(),
             y: self_xy.x * z_rot.sin() + self_xy.y * z_rot.cos(),
             z: self_xy.z,
-        };
-
-        self_xyz
+        }
     }
 }
 
@@ -93,6 +109,12 @@ pub const VEC3_ZERO: Vec3 = Vec3 {
     z: 0.,
 };
 
+impl Default for Vec3 {
+    fn default() -> Vec3 {
+        VEC3_ZERO
+    }
+}
+
 impl ops::Add<Vec3> for Vec3 {
     type Output = Vec3;
     fn add(self, other: Vec3) -> Vec3 {
@@ -123,3 +145,973 @@ impl ops::Neg for Vec3 {
         }
     }
 }
+
+impl ops::Mul<Float> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, s: Float) -> Vec3 {
+        self.scale(s)
+    }
+}
+
+impl ops::Mul<Vec3> for Float {
+    type Output = Vec3;
+    fn mul(self, v: Vec3) -> Vec3 {
+        v.scale(self)
+    }
+}
+
+impl ops::Div<Float> for Vec3 {
+    type Output = Vec3;
+    fn div(self, s: Float) -> Vec3 {
+        Vec3 {
+            x: self.x / s,
+            y: self.y / s,
+            z: self.z / s,
+        }
+    }
+}
+
+// component-wise product
+impl ops::Mul<Vec3> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x * other.x,
+            y: self.y * other.y,
+            z: self.z * other.z,
+        }
+    }
+}
+
+impl ops::AddAssign<Vec3> for Vec3 {
+    fn add_assign(&mut self, other: Vec3) {
+        *self = *self + other;
+    }
+}
+
+impl ops::SubAssign<Vec3> for Vec3 {
+    fn sub_assign(&mut self, other: Vec3) {
+        *self = *self - other;
+    }
+}
+
+impl ops::MulAssign<Float> for Vec3 {
+    fn mul_assign(&mut self, s: Float) {
+        *self = *self * s;
+    }
+}
+
+impl ops::DivAssign<Float> for Vec3 {
+    fn div_assign(&mut self, s: Float) {
+        *self = *self / s;
+    }
+}
+
+// components by axis, 0 is x, 1 is y and 2 is z
+impl ops::Index<usize> for Vec3 {
+    type Output = Float;
+    fn index(&self, axis: usize) -> &Float {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 has no axis {}", axis),
+        }
+    }
+}
+
+impl ops::IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, axis: usize) -> &mut Float {
+        match axis {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vec3 has no axis {}", axis),
+        }
+    }
+}
+
+impl Vec3 {
+    // component-wise minimum
+    pub fn min(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+            z: self.z.min(other.z),
+        }
+    }
+
+    // component-wise maximum
+    pub fn max(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x.max(other.x),
+            y: self.y.max(other.y),
+            z: self.z.max(other.z),
+        }
+    }
+
+    pub fn abs(self) -> Vec3 {
+        Vec3 {
+            x: self.x.abs(),
+            y: self.y.abs(),
+            z: self.z.abs(),
+        }
+    }
+
+    // self for s = 0, other for s = 1
+    pub fn lerp(self, other: Vec3, s: Float) -> Vec3 {
+        self + (other - self) * s
+    }
+
+    // mirrors the direction at a surface with the given unit normal
+    pub fn reflect(self, normal: Vec3) -> Vec3 {
+        self - normal * (2. * self.dot(normal))
+    }
+
+    // unit direction refracted at a surface with the given unit normal
+    // (facing against the direction) and ratio of refraction indices, None on
+    // total internal reflection
+    pub fn refract(self, normal: Vec3, eta: Float) -> Option<Vec3> {
+        let d = self.normalize();
+        let cos_i = -normal.dot(d);
+        let sin2_t = eta * eta * (1. - cos_i * cos_i);
+        if sin2_t > 1. {
+            return None;
+        }
+        let cos_t = (1. - sin2_t).sqrt();
+        Some(d * eta + normal * (eta * cos_i - cos_t))
+    }
+}
+
+// POINTS AND NORMALS
+// positions and surface normals transform differently from directions, these
+// types keep them apart where it matters. Point - Point is a direction and
+// directions can be added to points, but points cannot be added together.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Point3 {
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+pub const ORIGIN: Point3 = Point3 {
+    x: 0.,
+    y: 0.,
+    z: 0.,
+};
+
+impl Point3 {
+    pub fn distance(self, other: Point3) -> Float {
+        (self - other).norm()
+    }
+}
+
+impl From<Vec3> for Point3 {
+    fn from(v: Vec3) -> Point3 {
+        Point3 {
+            x: v.x,
+            y: v.y,
+            z: v.z,
+        }
+    }
+}
+
+impl From<Point3> for Vec3 {
+    fn from(p: Point3) -> Vec3 {
+        Vec3 {
+            x: p.x,
+            y: p.y,
+            z: p.z,
+        }
+    }
+}
+
+impl ops::Sub<Point3> for Point3 {
+    type Output = Vec3;
+    fn sub(self, other: Point3) -> Vec3 {
+        Vec3::from(self) - Vec3::from(other)
+    }
+}
+
+impl ops::Add<Vec3> for Point3 {
+    type Output = Point3;
+    fn add(self, v: Vec3) -> Point3 {
+        Point3::from(Vec3::from(self) + v)
+    }
+}
+
+impl ops::Sub<Vec3> for Point3 {
+    type Output = Point3;
+    fn sub(self, v: Vec3) -> Point3 {
+        Point3::from(Vec3::from(self) - v)
+    }
+}
+
+// unit length surface normal
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Normal3 {
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+impl Normal3 {
+    // normalizes the given direction
+    pub fn new(v: Vec3) -> Normal3 {
+        let v = v.normalize();
+        Normal3 {
+            x: v.x,
+            y: v.y,
+            z: v.z,
+        }
+    }
+
+    pub fn dot(self, v: Vec3) -> Float {
+        Vec3::from(self).dot(v)
+    }
+
+    // the normal flipped to the side of the given direction
+    pub fn face_forward(self, v: Vec3) -> Normal3 {
+        if self.dot(v) < 0. {
+            -self
+        } else {
+            self
+        }
+    }
+}
+
+impl From<Normal3> for Vec3 {
+    fn from(n: Normal3) -> Vec3 {
+        Vec3 {
+            x: n.x,
+            y: n.y,
+            z: n.z,
+        }
+    }
+}
+
+impl ops::Neg for Normal3 {
+    type Output = Normal3;
+    fn neg(self) -> Normal3 {
+        Normal3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+// image and texture coordinates
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Vec2 {
+    pub x: Float,
+    pub y: Float,
+}
+
+impl Vec2 {
+    pub fn dot(self, other: Vec2) -> Float {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn norm(self) -> Float {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(self) -> Vec2 {
+        self * (1. / self.norm())
+    }
+}
+
+impl ops::Add<Vec2> for Vec2 {
+    type Output = Vec2;
+    fn add(self, other: Vec2) -> Vec2 {
+        Vec2 {
+            x: self.x + other.x,
+            y: self.y + other.y,
+        }
+    }
+}
+
+impl ops::Sub<Vec2> for Vec2 {
+    type Output = Vec2;
+    fn sub(self, other: Vec2) -> Vec2 {
+        Vec2 {
+            x: self.x - other.x,
+            y: self.y - other.y,
+        }
+    }
+}
+
+impl ops::Neg for Vec2 {
+    type Output = Vec2;
+    fn neg(self) -> Vec2 {
+        Vec2 {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+
+impl ops::Mul<Float> for Vec2 {
+    type Output = Vec2;
+    fn mul(self, s: Float) -> Vec2 {
+        Vec2 {
+            x: self.x * s,
+            y: self.y * s,
+        }
+    }
+}
+
+impl ops::Div<Float> for Vec2 {
+    type Output = Vec2;
+    fn div(self, s: Float) -> Vec2 {
+        self * (1. / s)
+    }
+}
+
+// MATRICES
+// row-major, vectors are column vectors multiplied from the right
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat3 {
+    pub m: [[Float; 3]; 3],
+}
+
+pub const MAT3_IDENTITY: Mat3 = Mat3 {
+    m: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+};
+
+impl Mat3 {
+    pub fn from_columns(x: Vec3, y: Vec3, z: Vec3) -> Mat3 {
+        Mat3 {
+            m: [[x.x, y.x, z.x], [x.y, y.y, z.y], [x.z, y.z, z.z]],
+        }
+    }
+
+    pub fn scaling(s: Vec3) -> Mat3 {
+        Mat3 {
+            m: [[s.x, 0., 0.], [0., s.y, 0.], [0., 0., s.z]],
+        }
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let m = &self.m;
+        Mat3 {
+            m: [
+                [m[0][0], m[1][0], m[2][0]],
+                [m[0][1], m[1][1], m[2][1]],
+                [m[0][2], m[1][2], m[2][2]],
+            ],
+        }
+    }
+
+    pub fn determinant(&self) -> Float {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // None for singular matrices
+    pub fn inverse(&self) -> Option<Mat3> {
+        let det = self.determinant();
+        if det == 0. {
+            return None;
+        }
+        let m = &self.m;
+        // transposed cofactors
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            (m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]) / det
+        };
+        Some(Mat3 {
+            m: [
+                [
+                    cofactor(1, 2, 1, 2),
+                    -cofactor(0, 2, 1, 2),
+                    cofactor(0, 1, 1, 2),
+                ],
+                [
+                    -cofactor(1, 2, 0, 2),
+                    cofactor(0, 2, 0, 2),
+                    -cofactor(0, 1, 0, 2),
+                ],
+                [
+                    cofactor(1, 2, 0, 1),
+                    -cofactor(0, 2, 0, 1),
+                    cofactor(0, 1, 0, 1),
+                ],
+            ],
+        })
+    }
+}
+
+impl ops::Mul<Mat3> for Mat3 {
+    type Output = Mat3;
+    fn mul(self, other: Mat3) -> Mat3 {
+        let mut m = [[0.; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat3 { m }
+    }
+}
+
+impl ops::Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+    fn mul(self, v: Vec3) -> Vec3 {
+        let row = |r: [Float; 3]| r[0] * v.x + r[1] * v.y + r[2] * v.z;
+        Vec3 {
+            x: row(self.m[0]),
+            y: row(self.m[1]),
+            z: row(self.m[2]),
+        }
+    }
+}
+
+// affine and projective transforms of homogeneous coordinates
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4 {
+    pub m: [[Float; 4]; 4],
+}
+
+pub const MAT4_IDENTITY: Mat4 = Mat4 {
+    m: [
+        [1., 0., 0., 0.],
+        [0., 1., 0., 0.],
+        [0., 0., 1., 0.],
+        [0., 0., 0., 1.],
+    ],
+};
+
+impl Mat4 {
+    // linear part followed by a translation
+    pub fn affine(linear: Mat3, translation: Vec3) -> Mat4 {
+        let l = &linear.m;
+        Mat4 {
+            m: [
+                [l[0][0], l[0][1], l[0][2], translation.x],
+                [l[1][0], l[1][1], l[1][2], translation.y],
+                [l[2][0], l[2][1], l[2][2], translation.z],
+                [0., 0., 0., 1.],
+            ],
+        }
+    }
+
+    pub fn translation(t: Vec3) -> Mat4 {
+        Mat4::affine(MAT3_IDENTITY, t)
+    }
+
+    pub fn scaling(s: Vec3) -> Mat4 {
+        Mat4::affine(Mat3::scaling(s), VEC3_ZERO)
+    }
+
+    pub fn rotation(q: Quat) -> Mat4 {
+        Mat4::affine(q.to_mat3(), VEC3_ZERO)
+    }
+
+    // upper left 3x3 part, without translation and projection
+    pub fn linear(&self) -> Mat3 {
+        let m = &self.m;
+        Mat3 {
+            m: [
+                [m[0][0], m[0][1], m[0][2]],
+                [m[1][0], m[1][1], m[1][2]],
+                [m[2][0], m[2][1], m[2][2]],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Mat4 { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None for singular
+    // matrices
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inverse = MAT4_IDENTITY.m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column] == 0. {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1. / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for i in (0..4).filter(|&i| i != column) {
+                let factor = a[i][column];
+                for j in 0..4 {
+                    a[i][j] -= factor * a[column][j];
+                    inverse[i][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+        Some(Mat4 { m: inverse })
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        let row = |r: [Float; 4]| r[0] * p.x + r[1] * p.y + r[2] * p.z + r[3];
+        let w = row(m[3]);
+        Point3 {
+            x: row(m[0]) / w,
+            y: row(m[1]) / w,
+            z: row(m[2]) / w,
+        }
+    }
+
+    // directions are not translated
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        self.linear() * v
+    }
+
+    // normals are transformed by the inverse transpose, so they stay
+    // perpendicular to transformed surfaces
+    pub fn transform_normal(&self, n: Normal3) -> Normal3 {
+        let inverse = self.linear().inverse().unwrap_or(MAT3_IDENTITY);
+        Normal3::new(inverse.transpose() * Vec3::from(n))
+    }
+}
+
+impl ops::Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+// QUATERNIONS
+// unit quaternions represent rotations without the gimbal lock of Euler
+// angles, q * r rotates by r first and then by q
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quat {
+    pub w: Float,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
+}
+
+pub const QUAT_IDENTITY: Quat = Quat {
+    w: 1.,
+    x: 0.,
+    y: 0.,
+    z: 0.,
+};
+
+impl Quat {
+    // rotation by angle (in radians) around the given axis
+    pub fn from_axis_angle(axis: Vec3, angle: Float) -> Quat {
+        let axis = axis.normalize().scale((angle / 2.).sin());
+        Quat {
+            w: (angle / 2.).cos(),
+            x: axis.x,
+            y: axis.y,
+            z: axis.z,
+        }
+    }
+
+    // the rotation of Vec3::rotate: around x, then y, then z
+    pub fn from_euler(x_rot: Float, y_rot: Float, z_rot: Float) -> Quat {
+        let axis = |x, y, z| Vec3 { x, y, z };
+        Quat::from_axis_angle(axis(0., 0., 1.), z_rot)
+            * Quat::from_axis_angle(axis(0., 1., 0.), y_rot)
+            * Quat::from_axis_angle(axis(1., 0., 0.), x_rot)
+    }
+
+    pub fn dot(self, other: Quat) -> Float {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn norm(self) -> Float {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(self) -> Quat {
+        let s = 1. / self.norm();
+        Quat {
+            w: self.w * s,
+            x: self.x * s,
+            y: self.y * s,
+            z: self.z * s,
+        }
+    }
+
+    // inverse rotation of a unit quaternion
+    pub fn conjugate(self) -> Quat {
+        Quat {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn rotate(self, v: Vec3) -> Vec3 {
+        // v + 2 u x (u x v + w v), with u the vector part
+        let u = Vec3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let t = u.cross(v).scale(2.);
+        v + t.scale(self.w) + u.cross(t)
+    }
+
+    pub fn to_mat3(self) -> Mat3 {
+        let axis = |x, y, z| self.rotate(Vec3 { x, y, z });
+        Mat3::from_columns(axis(1., 0., 0.), axis(0., 1., 0.), axis(0., 0., 1.))
+    }
+
+    // spherical linear interpolation along the shorter arc, constant angular
+    // velocity for s from 0 to 1
+    pub fn slerp(self, other: Quat, s: Float) -> Quat {
+        let mut cos_theta = self.dot(other);
+        // q and -q are the same rotation
+        let other = if cos_theta < 0. {
+            cos_theta = -cos_theta;
+            -other
+        } else {
+            other
+        };
+
+        let (a, b) = if cos_theta > 0.9995 {
+            // nearly parallel, interpolate linearly instead of dividing by
+            // sin(theta) close to zero
+            (1. - s, s)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1. - s) * theta).sin() / sin_theta,
+                (s * theta).sin() / sin_theta,
+            )
+        };
+        Quat {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        }
+        .normalize()
+    }
+}
+
+impl ops::Mul<Quat> for Quat {
+    type Output = Quat;
+    fn mul(self, o: Quat) -> Quat {
+        Quat {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+}
+
+impl ops::Neg for Quat {
+    type Output = Quat;
+    fn neg(self) -> Quat {
+        Quat {
+            w: -self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V: Vec3 = Vec3 {
+        x: 1.,
+        y: -2.,
+        z: 3.,
+    };
+    const W: Vec3 = Vec3 {
+        x: 4.,
+        y: 5.,
+        z: -6.,
+    };
+
+    fn vec3(x: Float, y: Float, z: Float) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    #[test]
+    fn scalar_operators() {
+        assert_eq!(V * 2., vec3(2., -4., 6.));
+        assert_eq!(2. * V, V * 2.);
+        assert_eq!(V / 2., vec3(0.5, -1., 1.5));
+        assert_eq!(V * 2., V.scale(2.));
+    }
+
+    #[test]
+    fn component_wise_operators() {
+        assert_eq!(V + W, vec3(5., 3., -3.));
+        assert_eq!(V - W, vec3(-3., -7., 9.));
+        assert_eq!(V * W, vec3(4., -10., -18.));
+        assert_eq!(-V, vec3(-1., 2., -3.));
+    }
+
+    #[test]
+    fn assign_operators() {
+        let mut v = V;
+        v += W;
+        assert_eq!(v, V + W);
+        v -= W;
+        assert_eq!(v, V);
+        v *= 3.;
+        assert_eq!(v, V * 3.);
+        v /= 3.;
+        assert_eq!(v, V);
+    }
+
+    #[test]
+    fn indexing() {
+        let mut v = V;
+        assert_eq!((v[0], v[1], v[2]), (1., -2., 3.));
+        v[1] = 7.;
+        assert_eq!(v, vec3(1., 7., 3.));
+    }
+
+    #[test]
+    #[should_panic]
+    fn indexing_out_of_range() {
+        let _ = V[3];
+    }
+
+    #[test]
+    fn min_max_abs() {
+        assert_eq!(V.min(W), vec3(1., -2., -6.));
+        assert_eq!(V.max(W), vec3(4., 5., 3.));
+        assert_eq!(V.abs(), vec3(1., 2., 3.));
+    }
+
+    #[test]
+    fn lerp() {
+        assert_eq!(V.lerp(W, 0.), V);
+        assert_eq!(V.lerp(W, 1.), W);
+        assert_eq!(V.lerp(W, 0.5), vec3(2.5, 1.5, -1.5));
+    }
+
+    #[test]
+    fn reflect() {
+        let d = vec3(1., -1., 0.);
+        assert_eq!(d.reflect(vec3(0., 1., 0.)), vec3(1., 1., 0.));
+    }
+
+    #[test]
+    fn refract() {
+        let normal = vec3(0., 1., 0.);
+        // straight through at normal incidence
+        assert_eq!(
+            vec3(0., -1., 0.).refract(normal, 1.5),
+            Some(vec3(0., -1., 0.))
+        );
+        // Snell's law: sin(theta_t) = eta sin(theta_i)
+        let d = vec3(1., -1., 0.);
+        let t = d.refract(normal, 1. / 1.5).unwrap();
+        assert!((t.norm() - 1.).abs() < 1e-6);
+        assert!((t.x - (0.5 as Float).sqrt() / 1.5).abs() < 1e-6);
+        assert!(t.y < 0.);
+        // total internal reflection leaving a denser medium at a grazing angle
+        assert_eq!(vec3(1., -0.1, 0.).refract(normal, 1.5), None);
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).norm() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_quat_close(a: Quat, b: Quat) {
+        let d = [a.w - b.w, a.x - b.x, a.y - b.y, a.z - b.z];
+        assert!(d.iter().all(|d| d.abs() < 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    fn assert_identity<const N: usize>(m: [[Float; N]; N]) {
+        for (i, row) in m.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((value - expected).abs() < 1e-5, "{:?}", m);
+            }
+        }
+    }
+
+    // a rotation about an oblique axis
+    fn rotation() -> Quat {
+        Quat::from_axis_angle(vec3(1., 2., -1.), 0.7)
+    }
+
+    // rotation, non-uniform scaling and translation
+    fn transform() -> Mat4 {
+        Mat4::translation(vec3(3., -1., 2.))
+            * Mat4::rotation(rotation())
+            * Mat4::scaling(vec3(2., 0.5, 4.))
+    }
+
+    #[test]
+    fn points_and_normals() {
+        let p = Point3::from(V);
+        assert_eq!(p - ORIGIN, V);
+        assert_eq!(p + W - W, p);
+        assert_eq!(p.distance(Point3::from(W)), (V - W).norm());
+
+        let n = Normal3::new(vec3(0., 3., 4.));
+        assert_close(Vec3::from(n), vec3(0., 0.6, 0.8));
+        assert_eq!(n.face_forward(vec3(0., 1., 0.)), n);
+        assert_eq!(n.face_forward(vec3(0., -1., 0.)), -n);
+    }
+
+    #[test]
+    fn vec2() {
+        let v = Vec2 { x: 3., y: -4. };
+        assert_eq!(v.norm(), 5.);
+        assert!((v.normalize() - Vec2 { x: 0.6, y: -0.8 }).norm() < 1e-6);
+        assert_eq!(v.dot(Vec2 { x: 2., y: 1. }), 2.);
+        assert_eq!(v - v * 2., -v);
+    }
+
+    #[test]
+    fn mat3_inverse() {
+        let m = Mat3::from_columns(V, W, vec3(0.5, 1., 2.));
+        let inverse = m.inverse().unwrap();
+        assert_identity((m * inverse).m);
+        assert_identity((inverse * m).m);
+        assert!((m.determinant() * inverse.determinant() - 1.).abs() < 1e-5);
+        assert_eq!(MAT3_IDENTITY.inverse(), Some(MAT3_IDENTITY));
+        // linearly dependent columns
+        assert_eq!(Mat3::from_columns(V, W, V + W).inverse(), None);
+        assert_eq!(Mat3::scaling(vec3(1., 0., 2.)).inverse(), None);
+    }
+
+    #[test]
+    fn mat4_inverse() {
+        let m = transform();
+        let inverse = m.inverse().unwrap();
+        assert_identity((m * inverse).m);
+        assert_identity((inverse * m).m);
+        let p = Point3::from(V);
+        let q = inverse.transform_point(m.transform_point(p));
+        assert_close(q - ORIGIN, V);
+        // the translation only moves points
+        assert_close(m.transform_vector(W), m.linear() * W);
+        assert_close(m.transform_point(ORIGIN) - ORIGIN, vec3(3., -1., 2.));
+        // singular
+        assert_eq!(Mat4::scaling(vec3(1., 2., 0.)).inverse(), None);
+        let mut m = transform();
+        m.m[3] = [0.; 4];
+        assert_eq!(m.inverse(), None);
+    }
+
+    #[test]
+    fn transform_normal() {
+        let m = transform();
+        // the plane spanned by two tangents with its normal
+        let (u, v) = (vec3(1., 1., 0.), vec3(0., 1., 1.));
+        let n = m.transform_normal(Normal3::new(u.cross(v)));
+        assert!((Vec3::from(n).norm() - 1.).abs() < 1e-5);
+        assert!(n.dot(m.transform_vector(u)).abs() < 1e-5);
+        assert!(n.dot(m.transform_vector(v)).abs() < 1e-5);
+        // transforming the normal as a direction would tilt it
+        let direction = m.transform_vector(u.cross(v));
+        assert!(direction.dot(m.transform_vector(u)).abs() > 0.1);
+    }
+
+    #[test]
+    fn quat_rotation() {
+        let q = rotation();
+        assert!((q.norm() - 1.).abs() < 1e-6);
+        assert_close(q.conjugate().rotate(q.rotate(V)), V);
+        assert_close(q.to_mat3() * V, q.rotate(V));
+        assert_close(Mat4::rotation(q).transform_vector(V), q.rotate(V));
+        // q * r rotates by r first
+        let r = Quat::from_axis_angle(vec3(0., 0., 1.), 1.2);
+        assert_close((q * r).rotate(V), q.rotate(r.rotate(V)));
+    }
+
+    #[test]
+    fn from_euler_matches_vec3_rotate() {
+        for (x, y, z) in [
+            (0.3, 0., 0.),
+            (0., -1.1, 0.),
+            (0., 0., 2.),
+            (0.4, -1.3, 2.5),
+        ] {
+            assert_close(Quat::from_euler(x, y, z).rotate(V), V.rotate(x, y, z));
+        }
+    }
+
+    #[test]
+    fn slerp_endpoints() {
+        let (q, r) = (rotation(), Quat::from_euler(1., 0.5, -2.));
+        assert_quat_close(q.slerp(r, 0.), q);
+        assert_quat_close(q.slerp(r, 1.), r);
+        assert_quat_close(q.slerp(q, 0.5), q);
+    }
+
+    #[test]
+    fn slerp_constant_angular_velocity() {
+        let axis = vec3(0., 1., 0.);
+        let q = Quat::from_axis_angle(axis, 0.2);
+        let r = Quat::from_axis_angle(axis, 1.8);
+        for s in [0.25, 0.5, 0.75] {
+            let expected = Quat::from_axis_angle(axis, 0.2 + 1.6 * s);
+            assert_quat_close(q.slerp(r, s), expected);
+        }
+    }
+
+    #[test]
+    fn slerp_takes_the_shorter_arc() {
+        let q = rotation();
+        let r = Quat::from_euler(1., 0.5, -2.);
+        // -r is the same rotation
+        for s in [0.25, 0.5, 0.75] {
+            let a = q.slerp(r, s);
+            let b = q.slerp(-r, s);
+            assert_close(a.rotate(V), b.rotate(V));
+        }
+        // from 0 to 350 degrees goes back by 10 degrees, not forward by 350
+        let axis = vec3(0., 0., 1.);
+        let full = Quat::from_axis_angle(axis, 350. * PI / 180.);
+        let half = QUAT_IDENTITY.slerp(full, 0.5);
+        assert_close(
+            half.rotate(vec3(1., 0., 0.)),
+            vec3(1., 0., 0.).rotate(0., 0., -5. * PI / 180.),
+        );
+    }
+
+    // nearly equal rotations are interpolated linearly and normalized
+    #[test]
+    fn slerp_nearly_parallel() {
+        let axis = vec3(1., 1., 0.);
+        let q = Quat::from_axis_angle(axis, 0.5);
+        let r = Quat::from_axis_angle(axis, 0.51);
+        assert!(q.dot(r) > 0.9995);
+        for s in [0., 0.3, 0.5, 1.] {
+            let slerp = q.slerp(r, s);
+            assert!((slerp.norm() - 1.).abs() < 1e-6);
+            assert_quat_close(slerp, Quat::from_axis_angle(axis, 0.5 + 0.01 * s));
+        }
+    }
+}
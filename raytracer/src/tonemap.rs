@@ -0,0 +1,245 @@
+use wasm_bindgen::prelude::*;
+
+use super::color::*;
+use super::structs::*;
+
+// operators mapping unbounded scene-linear colors to displayable [0, 1]
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapper {
+    Clamp,
+    Reinhard,
+    AcesFilmic,
+    AgX,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMapping {
+    pub tone_mapper: ToneMapper,
+    // exposure adjustment in stops, applied before tone mapping
+    pub exposure: Float,
+}
+
+pub const DEFAULT_TONE_MAPPING: ToneMapping = ToneMapping {
+    tone_mapper: ToneMapper::AcesFilmic,
+    exposure: 0.,
+};
+
+impl ToneMapping {
+    // maps a scene-linear color to display-linear values in [0, 1]
+    pub fn apply(&self, color: Color) -> Color {
+        let c = color.scale((2. as Float).powf(self.exposure));
+        let (r, g, b) = match self.tone_mapper {
+            ToneMapper::Clamp => (c.r, c.g, c.b),
+            ToneMapper::Reinhard => reinhard(c),
+            ToneMapper::AcesFilmic => aces_filmic(c.r, c.g, c.b),
+            ToneMapper::AgX => agx(c.r, c.g, c.b),
+        };
+        Color {
+            r: r.clamp(0., 1.),
+            g: g.clamp(0., 1.),
+            b: b.clamp(0., 1.),
+            a: color.a,
+        }
+    }
+
+    // tone maps and encodes a color as 8 bit sRGB
+    pub fn to_srgb8(&self, color: Color) -> [u8; 3] {
+        let c = self.apply(color);
+        [encode_srgb8(c.r), encode_srgb8(c.g), encode_srgb8(c.b)]
+    }
+}
+
+pub fn linear_to_srgb(x: Float) -> Float {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    }
+}
+
+pub fn encode_srgb8(x: Float) -> u8 {
+    (linear_to_srgb(x.clamp(0., 1.)) * 255. + 0.5) as u8
+}
+
+// Reinhard on luminance, keeps the hue of overbright colors
+fn reinhard(c: Color) -> (Float, Float, Float) {
+    let l = c.luminance();
+    if l <= 0. {
+        return (0., 0., 0.);
+    }
+    let s = 1. / (1. + l);
+    (c.r * s, c.g * s, c.b * s)
+}
+
+fn mul3(m: &[[Float; 3]; 3], (r, g, b): (Float, Float, Float)) -> (Float, Float, Float) {
+    (
+        m[0][0] * r + m[0][1] * g + m[0][2] * b,
+        m[1][0] * r + m[1][1] * g + m[1][2] * b,
+        m[2][0] * r + m[2][1] * g + m[2][2] * b,
+    )
+}
+
+// ACES RRT + ODT fit by Stephen Hill
+fn aces_filmic(r: Float, g: Float, b: Float) -> (Float, Float, Float) {
+    const INPUT: [[Float; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[Float; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let fit = |v: Float| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.432951) + 0.238081;
+        a / b
+    };
+
+    let (r, g, b) = mul3(&INPUT, (r, g, b));
+    mul3(&OUTPUT, (fit(r), fit(g), fit(b)))
+}
+
+// AgX base look, using the polynomial contrast approximation by Benjamin Wrensch
+fn agx(r: Float, g: Float, b: Float) -> (Float, Float, Float) {
+    const INSET: [[Float; 3]; 3] = [
+        [0.8424791, 0.0784336, 0.07922375],
+        [0.04232824, 0.8784686, 0.07916613],
+        [0.04237565, 0.0784336, 0.879143],
+    ];
+    const OUTSET: [[Float; 3]; 3] = [
+        [1.196879, -0.09802088, -0.09902974],
+        [-0.05289685, 1.151903, -0.09896118],
+        [-0.05297164, -0.09804345, 1.151074],
+    ];
+    const MIN_EV: Float = -12.47393;
+    const MAX_EV: Float = 4.026069;
+    let contrast = |v: Float| {
+        let v = ((v.max(1e-10).log2() - MIN_EV) / (MAX_EV - MIN_EV)).clamp(0., 1.);
+        let v2 = v * v;
+        let v4 = v2 * v2;
+        15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v
+            - 0.00232
+    };
+
+    let (r, g, b) = mul3(&INSET, (r, g, b));
+    let (r, g, b) = mul3(&OUTSET, (contrast(r), contrast(g), contrast(b)));
+    // the curve produces display encoded values, return them linearized
+    (
+        r.max(0.).powf(2.2),
+        g.max(0.).powf(2.2),
+        b.max(0.).powf(2.2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONE_MAPPERS: [ToneMapper; 4] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::AcesFilmic,
+        ToneMapper::AgX,
+    ];
+
+    fn grey(v: Float) -> Color {
+        Color {
+            r: v,
+            g: v,
+            b: v,
+            a: 1.,
+        }
+    }
+
+    fn tone_mapping(tone_mapper: ToneMapper, exposure: Float) -> ToneMapping {
+        ToneMapping {
+            tone_mapper,
+            exposure,
+        }
+    }
+
+    #[test]
+    fn srgb_transfer_function() {
+        assert_eq!(linear_to_srgb(0.), 0.);
+        assert!((linear_to_srgb(1.) - 1.).abs() < 1e-6);
+        // both segments meet at the breakpoint
+        let below = linear_to_srgb(0.0031308);
+        let above = 1.055 * (0.0031308 as Float).powf(1. / 2.4) - 0.055;
+        assert!((below - 0.04045).abs() < 1e-5);
+        assert!((below - above).abs() < 1e-5);
+        assert!((linear_to_srgb(0.5) - 0.735357).abs() < 1e-5);
+        assert!((linear_to_srgb(0.001) - 0.01292).abs() < 1e-6);
+    }
+
+    #[test]
+    fn srgb8_encoding() {
+        assert_eq!(encode_srgb8(0.), 0);
+        assert_eq!(encode_srgb8(1.), 255);
+        assert_eq!(encode_srgb8(0.5), 188);
+        assert_eq!(encode_srgb8(0.0031308), 10);
+        // out of range values are clamped
+        assert_eq!(encode_srgb8(-1.), 0);
+        assert_eq!(encode_srgb8(7.), 255);
+        let codes: Vec<u8> = (0..=1000)
+            .map(|i| encode_srgb8(i as Float / 1000.))
+            .collect();
+        assert!(codes.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    // brighter inputs never map to darker outputs, and all outputs are
+    // displayable
+    #[test]
+    fn tone_mappers_are_monotonic_and_bounded() {
+        for tone_mapper in TONE_MAPPERS {
+            let mapping = tone_mapping(tone_mapper, 0.);
+            let mut previous = mapping.apply(grey(0.));
+            for i in 1..=400 {
+                let c = mapping.apply(grey((i as Float / 20.).powi(2) / 10.));
+                for (v, p) in [(c.r, previous.r), (c.g, previous.g), (c.b, previous.b)] {
+                    assert!((0. ..=1.).contains(&v), "{:?} gives {}", tone_mapper, v);
+                    assert!(v >= p - 1e-6, "{:?} decreases to {}", tone_mapper, v);
+                }
+                previous = c;
+            }
+        }
+    }
+
+    #[test]
+    fn tone_mappers_map_black_to_black() {
+        for tone_mapper in TONE_MAPPERS {
+            let c = tone_mapping(tone_mapper, 0.).apply(grey(0.));
+            assert!(c.r < 1e-3 && c.g < 1e-3 && c.b < 1e-3, "{:?}", tone_mapper);
+        }
+        let mapping = tone_mapping(ToneMapper::Reinhard, 0.);
+        assert_eq!(mapping.apply(grey(1.)).g, 0.5);
+    }
+
+    #[test]
+    fn tone_mappers_keep_alpha() {
+        for tone_mapper in TONE_MAPPERS {
+            let c = Color { a: 0.3, ..grey(2.) };
+            assert_eq!(tone_mapping(tone_mapper, 1.).apply(c).a, 0.3);
+        }
+    }
+
+    // every stop of exposure doubles the input
+    #[test]
+    fn exposure_scales_in_stops() {
+        for tone_mapper in TONE_MAPPERS {
+            for exposure in [-2., 1., 3.] {
+                let exposed = tone_mapping(tone_mapper, exposure).apply(grey(0.1));
+                let scaled =
+                    tone_mapping(tone_mapper, 0.).apply(grey(0.1 * (2. as Float).powf(exposure)));
+                assert!((exposed.g - scaled.g).abs() < 1e-6);
+            }
+        }
+        assert_eq!(
+            tone_mapping(ToneMapper::Clamp, -1.).apply(grey(0.5)).r,
+            0.25
+        );
+    }
+}
@@ -0,0 +1,129 @@
+// MOTION BLUR
+// rays at different times within the shutter see moving objects where their
+// keyframes put them at that time
+
+use raytracer::description::*;
+use raytracer::primitives::*;
+use raytracer::scene::*;
+use raytracer::structs::*;
+
+// a sphere of radius 0.2 with the given animated center
+fn scene(center: &str, time: Float) -> Scene {
+    let json = format!(
+        r#"{{
+            "camera": {{
+                "position": {{ "x": 0, "y": 0, "z": 0 }},
+                "rotation": {{ "x": 0, "y": 0, "z": 0 }}
+            }},
+            "lights": [],
+            "spheres": [{{
+                "center": {},
+                "radius": 0.2,
+                "options": {{}}
+            }}]
+        }}"#,
+        center
+    );
+    SceneDescription::from_json(&json).unwrap().at(time)
+}
+
+// whether a ray at time dt towards (x, y, 5) hits the sphere
+fn hits(scene: &Scene, x: Float, y: Float, dt: Float) -> bool {
+    let ray = Ray {
+        origin: VEC3_ZERO,
+        direction: Vec3 { x, y, z: 5. },
+        time: dt,
+    };
+    let hit = scene.spheres[0].intersect(&ray).is_some();
+    assert_eq!(scene.intersect_packet(&[ray])[0].is_some(), hit);
+    hit
+}
+
+// a step keyframe inside the shutter shows the sphere at both positions and
+// never in between
+#[test]
+fn steps_within_the_shutter() {
+    let scene = scene(
+        r#"{ "keyframes": [
+            { "time": 0, "value": { "x": -1, "y": 0, "z": 5 } },
+            { "time": 1, "value": { "x": 1, "y": 0, "z": 5 }, "interpolation": "Step" }
+        ] }"#,
+        0.95,
+    );
+    for dt in [-0.1, -0.01, 0.049] {
+        assert!(hits(&scene, -1., 0., dt));
+        assert!(!hits(&scene, 1., 0., dt));
+    }
+    for dt in [0.051, 0.06, 0.1] {
+        assert!(!hits(&scene, -1., 0., dt));
+        assert!(hits(&scene, 1., 0., dt));
+    }
+    for dt in [-0.1, 0., 0.049, 0.05, 0.1] {
+        assert!(!hits(&scene, 0., 0., dt));
+    }
+}
+
+// linear keyframes move the sphere at constant speed
+#[test]
+fn linear_motion() {
+    let scene = scene(
+        r#"{ "keyframes": [
+            { "time": 0, "value": { "x": 0, "y": 0, "z": 5 } },
+            { "time": 1, "value": { "x": 2, "y": 0, "z": 5 } }
+        ] }"#,
+        0.5,
+    );
+    assert_eq!(scene.spheres[0].center.x, 1.);
+    for dt in [-0.25, 0., 0.1, 0.25] {
+        let center = scene.spheres[0].center_at(dt);
+        assert!((center.x - (1. + 2. * dt)).abs() < 1e-5);
+        assert!(hits(&scene, center.x, 0., dt));
+        assert!(!hits(&scene, center.x + 0.5, 0., dt));
+    }
+}
+
+// blur along a Bezier arc follows the arc instead of its tangent
+#[test]
+fn curved_motion() {
+    let scene = scene(
+        r#"{ "keyframes": [
+            { "time": 0, "value": { "x": -1, "y": 0, "z": 5 },
+              "out_control": { "x": -1, "y": 4, "z": 5 } },
+            { "time": 1, "value": { "x": 1, "y": 0, "z": 5 }, "interpolation": "Bezier",
+              "in_control": { "x": 1, "y": 4, "z": 5 } }
+        ] }"#,
+        0.5,
+    );
+    // at the top of the arc at t = 0.5, and lower on both sides
+    let top = scene.spheres[0].center;
+    assert!((top.y - 3.).abs() < 1e-5);
+    for dt in [-0.3, 0.3] {
+        let center = scene.spheres[0].center_at(dt);
+        assert!(center.y < top.y - 0.5);
+        assert!(hits(&scene, center.x, center.y, dt));
+        // the tangent at the top is horizontal
+        assert!(!hits(&scene, center.x, top.y, dt));
+    }
+}
+
+// repeated keyframes jump back to the start when the shutter spans the end
+// of the loop, held ones stay at the end
+#[test]
+fn repeated_motion() {
+    let keyframes = r#"[
+        { "time": 0, "value": { "x": -1, "y": 0, "z": 5 } },
+        { "time": 1, "value": { "x": 1, "y": 0, "z": 5 } }
+    ]"#;
+    let repeated = scene(
+        &format!(r#"{{ "keyframes": {}, "repeat": true }}"#, keyframes),
+        0.95,
+    );
+    assert!(hits(&repeated, 0.9, 0., 0.));
+    assert!(hits(&repeated, -0.9, 0., 0.1));
+    assert!(!hits(&repeated, 1., 0., 0.1));
+
+    let held = scene(&format!(r#"{{ "keyframes": {} }}"#, keyframes), 0.95);
+    assert!(hits(&held, 0.9, 0., 0.));
+    assert!(hits(&held, 1., 0., 0.1));
+    assert!(!hits(&held, -0.9, 0., 0.1));
+}
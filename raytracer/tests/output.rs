@@ -0,0 +1,270 @@
+// image writers, read back with an independent OpenEXR decoder or decoded
+// by hand for the simpler formats
+
+// EXR floats are single precision regardless of Float
+#![allow(clippy::unnecessary_cast)]
+
+use std::io::Cursor;
+
+use exr::image::{AnyChannels, FlatSamples, Image, Layer};
+use exr::prelude::{f16, read, ReadChannels, ReadLayers};
+
+use raytracer::color::*;
+use raytracer::output::*;
+use raytracer::structs::*;
+
+const WIDTH: usize = 5;
+const HEIGHT: usize = 3;
+
+fn pixels() -> Vec<Color> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| Color {
+            r: i as Float * 0.25,
+            g: 1000. / (i + 1) as Float,
+            b: -(i as Float),
+            a: 1. - i as Float / 16.,
+        })
+        .collect()
+}
+
+fn read_exr(bytes: Vec<u8>) -> Image<Layer<AnyChannels<FlatSamples>>> {
+    read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .all_channels()
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(Cursor::new(bytes))
+        .unwrap()
+}
+
+fn exr(layers: &[(&str, Vec<Color>)], pixel_type: PixelType) -> Vec<u8> {
+    let channels: Vec<ExrChannel> = layers
+        .iter()
+        .flat_map(|(layer, pixels)| color_channels(layer, pixels))
+        .collect();
+    let mut bytes = Vec::new();
+    write_exr(&mut bytes, WIDTH, HEIGHT, &channels, pixel_type).unwrap();
+    bytes
+}
+
+// values of the named channel, as single precision floats
+fn channel(image: &Image<Layer<AnyChannels<FlatSamples>>>, name: &str) -> Vec<f32> {
+    let channel = image
+        .layer_data
+        .channel_data
+        .list
+        .iter()
+        .find(|channel| channel.name == *name)
+        .unwrap_or_else(|| panic!("no channel {}", name));
+    match &channel.sample_data {
+        FlatSamples::F16(values) => values.iter().map(|v| v.to_f32()).collect(),
+        FlatSamples::F32(values) => values.clone(),
+        FlatSamples::U32(_) => panic!("channel {} has integer samples", name),
+    }
+}
+
+#[test]
+fn exr_round_trip() {
+    let pixels = pixels();
+    let image = read_exr(exr(&[("", pixels.clone())], PixelType::Float));
+    assert_eq!(image.layer_data.size.0, WIDTH);
+    assert_eq!(image.layer_data.size.1, HEIGHT);
+
+    let expected = |value: fn(&Color) -> Float| -> Vec<f32> {
+        pixels.iter().map(|c| value(c) as f32).collect()
+    };
+    assert_eq!(channel(&image, "R"), expected(|c| c.r));
+    assert_eq!(channel(&image, "G"), expected(|c| c.g));
+    assert_eq!(channel(&image, "B"), expected(|c| c.b));
+    assert_eq!(channel(&image, "A"), expected(|c| c.a));
+}
+
+#[test]
+fn exr_half_round_trip() {
+    let pixels = pixels();
+    let image = read_exr(exr(&[("", pixels.clone())], PixelType::Half));
+    let expected: Vec<f32> = pixels
+        .iter()
+        .map(|c| f16::from_bits(f32_to_f16(c.g as f32)).to_f32())
+        .collect();
+    assert_eq!(channel(&image, "G"), expected);
+    // quarters are exact in half precision
+    for (value, c) in channel(&image, "R").iter().zip(&pixels) {
+        assert_eq!(*value, c.r as f32);
+    }
+}
+
+#[test]
+fn exr_layers() {
+    let pixels = pixels();
+    let normals: Vec<Color> = pixels.iter().map(|c| c.scale(0.5)).collect();
+    let image = read_exr(exr(
+        &[("", pixels.clone()), ("normal", normals.clone())],
+        PixelType::Float,
+    ));
+    assert_eq!(image.layer_data.channel_data.list.len(), 8);
+    assert_eq!(
+        channel(&image, "normal.R"),
+        normals.iter().map(|c| c.r as f32).collect::<Vec<f32>>()
+    );
+    assert_eq!(
+        channel(&image, "R"),
+        pixels.iter().map(|c| c.r as f32).collect::<Vec<f32>>()
+    );
+}
+
+#[test]
+fn exr_rejects_channels_of_the_wrong_size() {
+    let channels = color_channels("", &pixels()[1..]);
+    let result = write_exr(&mut Vec::new(), WIDTH, HEIGHT, &channels, PixelType::Half);
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn half_known_values() {
+    for (value, bits) in [
+        (0., 0x0000),
+        (-0., 0x8000),
+        (1., 0x3c00),
+        (-2., 0xc000),
+        (0.5, 0x3800),
+        (0.1, 0x2e66),
+        (65504., 0x7bff),
+        // smallest normal
+        (6.1035156e-5, 0x0400),
+    ] {
+        assert_eq!(f32_to_f16(value), bits, "{}", value);
+    }
+}
+
+#[test]
+fn half_subnormals() {
+    let tiny = 2_f32.powi(-24);
+    assert_eq!(f32_to_f16(tiny), 0x0001);
+    assert_eq!(f32_to_f16(-tiny), 0x8001);
+    assert_eq!(f32_to_f16(1023. * tiny), 0x03ff);
+    assert_eq!(f32_to_f16(3.5 * tiny), 0x0004);
+    // halfway cases round to even
+    assert_eq!(f32_to_f16(0.5 * tiny), 0x0000);
+    assert_eq!(f32_to_f16(1.5 * tiny), 0x0002);
+    assert_eq!(f32_to_f16(2.5 * tiny), 0x0002);
+    // too small for half precision
+    assert_eq!(f32_to_f16(0.25 * tiny), 0x0000);
+    assert_eq!(f32_to_f16(-1e-30), 0x8000);
+    assert_eq!(f32_to_f16(f32::MIN_POSITIVE), 0x0000);
+}
+
+#[test]
+fn half_overflow_and_nan() {
+    assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+    assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+    assert_eq!(f32_to_f16(1e6), 0x7c00);
+    assert_eq!(f32_to_f16(-1e6), 0xfc00);
+    assert_eq!(f32_to_f16(f32::MAX), 0x7c00);
+    // rounds up past the largest half
+    assert_eq!(f32_to_f16(65520.), 0x7c00);
+    assert_eq!(f32_to_f16(65519.), 0x7bff);
+
+    let nan = f32_to_f16(f32::NAN);
+    assert_eq!(nan & 0x7c00, 0x7c00);
+    assert_ne!(nan & 0x3ff, 0);
+    assert!(f16::from_bits(f32_to_f16(-f32::NAN)).is_nan());
+}
+
+// the conversion agrees with the half crate for bit patterns spread over
+// all exponents
+#[test]
+fn half_matches_reference() {
+    for i in 0..=0xffff_u32 {
+        let value = f32::from_bits(i.wrapping_mul(0x9e37_79b9) ^ (i << 16));
+        let expected = f16::from_f32(value);
+        let bits = f32_to_f16(value);
+        if value.is_nan() {
+            assert!(f16::from_bits(bits).is_nan());
+        } else {
+            assert_eq!(bits, expected.to_bits(), "{:e}", value);
+        }
+    }
+}
+
+fn hdr(pixels: &[Color], width: usize, height: usize) -> (String, Vec<u8>) {
+    let mut bytes = Vec::new();
+    write_hdr(&mut bytes, width, height, pixels).unwrap();
+    let end = bytes.windows(2).position(|w| w == b"\n\n").unwrap() + 2;
+    let end = end + bytes[end..].iter().position(|&b| b == b'\n').unwrap() + 1;
+    let data = bytes.split_off(end);
+    (String::from_utf8(bytes).unwrap(), data)
+}
+
+fn rgbe_to_color(rgbe: &[u8]) -> Color {
+    if rgbe[3] == 0 {
+        return COLOR_ZERO;
+    }
+    let scale = (2. as Float).powi(rgbe[3] as i32 - 136);
+    Color {
+        r: (rgbe[0] as Float + 0.5) * scale,
+        g: (rgbe[1] as Float + 0.5) * scale,
+        b: (rgbe[2] as Float + 0.5) * scale,
+        a: 1.,
+    }
+}
+
+#[test]
+fn rgbe_header() {
+    let (header, data) = hdr(&pixels(), WIDTH, HEIGHT);
+    assert_eq!(header, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 3 +X 5\n");
+    assert_eq!(data.len(), 4 * WIDTH * HEIGHT);
+}
+
+#[test]
+fn rgbe_rejects_pixels_of_the_wrong_size() {
+    for pixels in [&pixels()[1..], &[pixels(), pixels()].concat()] {
+        let mut bytes = Vec::new();
+        let result = write_hdr(&mut bytes, WIDTH, HEIGHT, pixels);
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        // nothing is written
+        assert!(bytes.is_empty());
+    }
+}
+
+#[test]
+fn rgbe_known_values() {
+    let color = |r: Float, g: Float, b: Float| Color { r, g, b, a: 1. };
+    let (_, data) = hdr(
+        &[
+            COLOR_ZERO,
+            color(1., 1., 1.),
+            color(0.5, 0.25, 0.),
+            color(-1., 2., 0.),
+            color(1e-40, 0., 0.),
+        ],
+        5,
+        1,
+    );
+    // zero is stored with a zero exponent
+    assert_eq!(data[0..4], [0, 0, 0, 0]);
+    assert_eq!(data[4..8], [128, 128, 128, 129]);
+    assert_eq!(data[8..12], [128, 64, 0, 128]);
+    // negative components are clamped
+    assert_eq!(data[12..16], [0, 128, 0, 130]);
+    assert_eq!(data[16..20], [0, 0, 0, 0]);
+}
+
+#[test]
+fn rgbe_round_trip() {
+    for c in pixels() {
+        let (_, data) = hdr(&[c], 1, 1);
+        let decoded = rgbe_to_color(&data);
+        // the mantissas are 8 bit relative to the largest component
+        let max = c.r.max(c.g).max(c.b);
+        for (value, expected) in [(decoded.r, c.r), (decoded.g, c.g), (decoded.b, c.b)] {
+            assert!(
+                (value - expected.max(0.)).abs() <= max / 128.,
+                "{} decoded as {}",
+                expected,
+                value
+            );
+        }
+    }
+}
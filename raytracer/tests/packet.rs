@@ -0,0 +1,77 @@
+use raytracer::camera::*;
+use raytracer::demo::*;
+use raytracer::description::*;
+use raytracer::packet::*;
+use raytracer::render::*;
+use raytracer::sampling::*;
+use raytracer::structs::*;
+
+const WIDTH: usize = 96;
+const HEIGHT: usize = 54;
+
+// primary rays of the camera, followed by rays from random points in random
+// directions at random times within a second
+fn rays(camera: &Camera) -> Vec<Ray> {
+    let mut rng = Rng::new(1);
+    let mut rays: Vec<Ray> = (0..HEIGHT)
+        .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+        .map(|(x, y)| image_ray(camera, WIDTH, HEIGHT, x as Float + 0.5, y as Float + 0.5))
+        .collect();
+    let mut random = |scale: Float| (rng.next_float() - 0.5) * scale;
+    for _ in 0..2000 {
+        rays.push(Ray {
+            origin: Vec3 {
+                x: random(8.),
+                y: random(8.),
+                z: random(8.),
+            },
+            direction: Vec3 {
+                x: random(2.),
+                y: random(2.),
+                z: random(2.),
+            },
+            time: random(1.),
+        });
+    }
+    rays
+}
+
+// packets find exactly the intersections found ray by ray, also in packets
+// that are not full
+fn assert_packets_match(description: &SceneDescription, time: Float) {
+    let scene = description.at(time);
+    let camera = description.camera(VEC3_ZERO, 0., 0., 0., 1.4, WIDTH, HEIGHT, time);
+    let rays = rays(&camera);
+    for size in [LANES, LANES / 2 + 1] {
+        for packet in rays.chunks(size) {
+            let intersections = scene.intersect_packet(packet);
+            for (lane, ray) in packet.iter().enumerate() {
+                assert_eq!(
+                    intersections[lane],
+                    scene.intersect_object(ray),
+                    "ray from {:?} along {:?}",
+                    ray.origin,
+                    ray.direction
+                );
+            }
+            assert!(intersections[packet.len()..].iter().all(Option::is_none));
+        }
+    }
+}
+
+#[test]
+fn packets_match_single_rays() {
+    // moving objects at later times
+    for time in [0., 1., 2.5] {
+        assert_packets_match(&demo_scene(), time);
+    }
+    for preset in ["fog", "glass", "mirrors"] {
+        let path = format!(
+            "{}/../web/scenes/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            preset
+        );
+        let json = std::fs::read_to_string(path).unwrap();
+        assert_packets_match(&SceneDescription::from_json(&json).unwrap(), 0.);
+    }
+}
@@ -0,0 +1,120 @@
+use raytracer::animation::*;
+use raytracer::color::*;
+use raytracer::primitives::*;
+use raytracer::scene::*;
+use raytracer::structs::*;
+
+// a sphere lit from above at the given offset from the origin
+fn scene(o: Vec3) -> Scene {
+    Scene {
+        lights: vec![LightSource {
+            pos: o + Vec3 {
+                x: 0.,
+                y: 50.,
+                z: 0.,
+            },
+            color: COLOR_WHITE,
+        }],
+        spheres: vec![Sphere {
+            center: o,
+            radius: 10.,
+            options: OPTIONS,
+            motion: NO_MOTION,
+        }],
+        boards: vec![],
+        fog: None,
+    }
+}
+
+// a grid of rays hitting the lit top of the sphere at the given offset from
+// the origin, counts how many of them are wrongly shadowed by the sphere
+// itself
+fn count_shadowed(offset: Float, shadowed: impl Fn(&Scene, &Ray) -> bool) -> usize {
+    let o = Vec3 {
+        x: offset,
+        y: offset,
+        z: offset,
+    };
+    let scene = scene(o);
+
+    let n = 32;
+    let mut count = 0;
+    for i in 0..n {
+        for j in 0..n {
+            let ray = Ray {
+                origin: o + Vec3 {
+                    x: (i as Float / n as Float - 0.5) * 10.,
+                    y: 30.,
+                    z: (j as Float / n as Float - 0.5) * 10.,
+                },
+                direction: Vec3 {
+                    x: 0.01,
+                    y: -1.,
+                    z: 0.013,
+                },
+                time: 0.,
+            };
+            if shadowed(&scene, &ray) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+fn shadow_acne(offset: Float) -> usize {
+    count_shadowed(offset, |scene, ray| {
+        let sample = scene.shade_sample(ray, &DEFAULT_RENDER_SETTINGS);
+        assert!(sample.hit.is_some());
+        sample.direct.b == 0.
+    })
+}
+
+// the same with shadow rays starting at a fixed distance from the surface
+// instead of past the rounding error of the hit point
+fn fixed_bias_acne(offset: Float) -> usize {
+    const BIAS: Float = 1e-5;
+    count_shadowed(offset, |scene, ray| {
+        let (_, point, normal, _) = scene.intersect(ray).unwrap();
+        let origin = point + normal.scale(BIAS);
+        let shadow_ray = Ray {
+            origin,
+            direction: scene.lights[0].pos - origin,
+            time: ray.time,
+        };
+        scene
+            .intersect(&shadow_ray)
+            .is_some_and(|(t, ..)| t > 0. && t < 1.)
+    })
+}
+
+// shadow rays start offset by the rounding error of the hit point, which
+// grows with its distance from the origin, so not even single precision
+// shows acne far away from it
+#[test]
+fn no_shadow_acne_at_large_coordinates() {
+    for offset in [0., 1e3, 1e5, 1e6] {
+        assert_eq!(shadow_acne(offset), 0, "offset {}", offset);
+    }
+}
+
+// without the robust offset a fixed bias is enough in double precision, even
+// a million units from the origin
+#[cfg(feature = "f64")]
+#[test]
+fn fixed_bias_suffices_in_double_precision() {
+    for offset in [0., 1e3, 1e5, 1e6] {
+        assert_eq!(fixed_bias_acne(offset), 0, "offset {}", offset);
+    }
+}
+
+// while single precision hit points a thousand units out already land on the
+// wrong side of the surface for about half of the rays
+#[cfg(not(feature = "f64"))]
+#[test]
+fn fixed_bias_shows_acne_in_single_precision() {
+    assert_eq!(fixed_bias_acne(0.), 0);
+    for offset in [1e3, 1e5, 1e6] {
+        assert!(fixed_bias_acne(offset) > 100, "offset {}", offset);
+    }
+}
@@ -0,0 +1,261 @@
+// REFERENCE IMAGES
+// renders canonical scenes and compares them with the images in
+// tests/references by their structural similarity (SSIM), which tolerates
+// noise and rounding but not changed geometry or shading. On failure, the
+// render and a diff image are written next to the test binaries (see the
+// panic message). After intended changes to the output, rerun with
+// UPDATE_REFERENCES=1 to replace the references.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use raytracer::demo::*;
+use raytracer::denoise::*;
+use raytracer::description::*;
+use raytracer::film::*;
+use raytracer::output::*;
+use raytracer::render::*;
+use raytracer::sampling::*;
+use raytracer::scene::*;
+use raytracer::structs::*;
+use raytracer::tonemap::*;
+
+const WIDTH: usize = 160;
+const HEIGHT: usize = 90;
+
+// scenes with media are noisy enough at few samples that the noise alone
+// changes their SSIM, they are denoised instead
+#[derive(Debug, Copy, Clone)]
+struct Quality {
+    samples_per_pixel: usize,
+    denoise: bool,
+}
+
+const PLAIN: Quality = Quality {
+    samples_per_pixel: 4,
+    denoise: false,
+};
+
+const DENOISED: Quality = Quality {
+    samples_per_pixel: 16,
+    denoise: true,
+};
+
+// 80 degrees, as in the renderer
+const FOV: Float = 1.396263;
+
+// mean SSIM below which a render differs from its reference
+const MIN_SSIM: f64 = 0.98;
+
+// checkerboards along all three axes, with a sphere reflecting them
+const BOARDS: &str = r#"{
+    "camera": {
+        "position": { "x": 0.5, "y": 1.2, "z": -1 },
+        "rotation": { "x": 20, "y": 0, "z": 0 }
+    },
+    "lights": [{ "pos": { "x": 1, "y": 3, "z": 1 }, "color": { "r": 1, "g": 1, "b": 1, "a": 1 } }],
+    "spheres": [{
+        "center": { "x": 0, "y": 0, "z": 3 },
+        "radius": 0.6,
+        "options": { "reflective_part": 0.5 }
+    }],
+    "boards": [
+        {
+            "axis": "XAxis", "pos": { "x": -2, "y": 0, "z": 3 },
+            "radius": 4, "grid_size": 0.5, "options": {}
+        },
+        {
+            "axis": "YAxis", "pos": { "x": 0, "y": -0.6, "z": 3 },
+            "radius": 4, "grid_size": 0.5, "options": {}
+        },
+        {
+            "axis": "ZAxis", "pos": { "x": 0, "y": 0, "z": 6 },
+            "radius": 4, "grid_size": 0.5, "options": {}
+        }
+    ]
+}"#;
+
+fn render(description: &SceneDescription, time: Float, quality: Quality) -> Vec<u8> {
+    let scene = description.at(time);
+    let camera = description.camera(VEC3_ZERO, 0., 0., 0., FOV, WIDTH, HEIGHT, time);
+    let mut film = Film::new(WIDTH, HEIGHT, 0, WIDTH, 0, HEIGHT, Filter::Gaussian);
+    if quality.denoise {
+        film.enable_aovs();
+    }
+    render_film(
+        &scene,
+        &camera,
+        &SobolSampler,
+        quality.samples_per_pixel,
+        &DEFAULT_RENDER_SETTINGS,
+        &mut film,
+    );
+    if quality.denoise {
+        denoise(&mut film, &DEFAULT_DENOISE_SETTINGS);
+    }
+    film.develop(&DEFAULT_TONE_MAPPING)
+}
+
+// relative luminance of the RGBA pixels, in [0, 1]
+fn luminance(rgba: &[u8]) -> Vec<f64> {
+    rgba.chunks(4)
+        .map(|p| (0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64) / 255.)
+        .collect()
+}
+
+// mean structural similarity of the luminance over 8x8 windows, 1 for
+// identical images
+fn ssim(a: &[u8], b: &[u8]) -> f64 {
+    const WINDOW: usize = 8;
+    const STRIDE: usize = 4;
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let (a, b) = (luminance(a), luminance(b));
+    let n = (WINDOW * WINDOW) as f64;
+    let mut total = 0.;
+    let mut windows = 0;
+    for y in (0..=HEIGHT - WINDOW).step_by(STRIDE) {
+        for x in (0..=WIDTH - WINDOW).step_by(STRIDE) {
+            let pixels =
+                || (y..y + WINDOW).flat_map(move |y| (x..x + WINDOW).map(move |x| y * WIDTH + x));
+            let mean_a = pixels().map(|i| a[i]).sum::<f64>() / n;
+            let mean_b = pixels().map(|i| b[i]).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut cov) = (0., 0., 0.);
+            for i in pixels() {
+                let (da, db) = (a[i] - mean_a, b[i] - mean_b);
+                var_a += da * da / n;
+                var_b += db * db / n;
+                cov += da * db / n;
+            }
+            total += (2. * mean_a * mean_b + C1) * (2. * cov + C2)
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+// absolute differences, amplified so small ones are visible
+fn diff_image(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.chunks(4)
+        .zip(b.chunks(4))
+        .flat_map(|(p, q)| {
+            let d = |i: usize| (p[i].abs_diff(q[i]) as u32 * 4).min(255) as u8;
+            [d(0), d(1), d(2), 255]
+        })
+        .collect()
+}
+
+fn write(path: &Path, rgba: &[u8]) {
+    let file = File::create(path).unwrap();
+    write_png(BufWriter::new(file), WIDTH, HEIGHT, rgba).unwrap();
+}
+
+fn check_reference(name: &str, description: &SceneDescription, time: Float, quality: Quality) {
+    let image = render(description, time, quality);
+    let reference_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/references")
+        .join(format!("{}.png", name));
+
+    if std::env::var_os("UPDATE_REFERENCES").is_some() {
+        fs::create_dir_all(reference_path.parent().unwrap()).unwrap();
+        write(&reference_path, &image);
+        return;
+    }
+
+    let failures = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("reference-failures");
+    fs::create_dir_all(&failures).unwrap();
+    let render_path = failures.join(format!("{}.png", name));
+
+    let reference = match File::open(&reference_path) {
+        Ok(file) => read_png(BufReader::new(file)).unwrap(),
+        Err(_) => {
+            write(&render_path, &image);
+            panic!(
+                "{}: no reference image at {}, the render is at {}, rerun with UPDATE_REFERENCES=1 to add it",
+                name,
+                reference_path.display(),
+                render_path.display()
+            );
+        }
+    };
+    assert_eq!(
+        (reference.0, reference.1),
+        (WIDTH, HEIGHT),
+        "{}: reference image has the wrong size",
+        name
+    );
+
+    let similarity = ssim(&image, &reference.2);
+    if similarity < MIN_SSIM {
+        let diff_path = failures.join(format!("{}-diff.png", name));
+        write(&render_path, &image);
+        write(&diff_path, &diff_image(&image, &reference.2));
+        panic!(
+            "{}: SSIM {:.4} below {}, the render is at {} and the difference at {}",
+            name,
+            similarity,
+            MIN_SSIM,
+            render_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn preset(name: &str) -> SceneDescription {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../web/scenes")
+        .join(format!("{}.json", name));
+    SceneDescription::from_json(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn demo() {
+    check_reference("demo", &demo_scene(), 0., PLAIN);
+}
+
+#[test]
+fn demo_animated() {
+    check_reference("demo-2s", &demo_scene(), 2., PLAIN);
+}
+
+#[test]
+fn boards() {
+    check_reference(
+        "boards",
+        &SceneDescription::from_json(BOARDS).unwrap(),
+        0.,
+        PLAIN,
+    );
+}
+
+#[test]
+fn fog() {
+    check_reference("fog", &preset("fog"), 0., DENOISED);
+}
+
+#[test]
+fn glass() {
+    check_reference("glass", &preset("glass"), 0., DENOISED);
+}
+
+#[test]
+fn mirrors() {
+    check_reference("mirrors", &preset("mirrors"), 0., PLAIN);
+}
+
+// the comparison itself: identical images match, inverted ones do not
+#[test]
+fn ssim_detects_changes() {
+    let description = SceneDescription::from_json(BOARDS).unwrap();
+    let image = render(&description, 0., PLAIN);
+    assert!((ssim(&image, &image) - 1.).abs() < 1e-9);
+
+    let inverted: Vec<u8> = image
+        .chunks(4)
+        .flat_map(|p| [255 - p[0], 255 - p[1], 255 - p[2], p[3]])
+        .collect();
+    assert!(ssim(&image, &inverted) < MIN_SSIM);
+}
@@ -19,19 +19,80 @@ let visible_buf = 0;
 buffers[visible_buf].canvas.style.visibility = "visible";
 buffers[1 - visible_buf].canvas.style.visibility = "hidden";
 
-// setup workers
-const workers = [];
-for (let i = 0; i < 4; i++) {
-  workers[i] = new Worker("worker.js");
+// setup workers: a single worker rendering on all cores if the threaded build
+// is available and the page is cross-origin isolated, otherwise one
+// single-threaded worker per core, rendering tiles from a queue
+let workers = [];
+let workerSceneVersions = [];
+
+const TILE_SIZE = 64;
+// denoised tiles are rendered with a 62 pixel apron, which is cheaper
+// relative to larger tiles, see render_region in src/lib.rs
+const DENOISE_TILE_SIZE = 256;
+
+// while refining, every tile stays with the worker that accumulated its
+// previous passes
+let tileOwners = new Map();
+let tilePasses = new Map();
+let tileLayout = null;
+
+function startThreadedWorker() {
+  return new Promise((resolve) => {
+    if (!self.crossOriginIsolated) return resolve(null);
+    const worker = new Worker("threads-worker.js", { type: "module" });
+    worker.onmessage = (msg) => {
+      if (msg.data.ready) return resolve(worker);
+      console.warn("falling back to single-threaded workers:", msg.data.error);
+      worker.terminate();
+      resolve(null);
+    };
+    // pkg-threads has not been built
+    worker.onerror = () => {
+      worker.terminate();
+      resolve(null);
+    };
+  });
+}
+
+async function startWorkers() {
+  const threadedWorker = await startThreadedWorker();
+  workers =
+    threadedWorker !== null
+      ? [threadedWorker]
+      : Array.from(
+          { length: navigator.hardwareConcurrency || 4 },
+          () => new Worker("worker.js")
+        );
+  workerSceneVersions = workers.map(() => 0);
 }
 
 let animating = true; // animation running or not, changed via play/pause button
 let renderingInput = false; // if scene is actively rerendered due to input
 
+// while the view does not change, every frame adds samples to the previous
+// ones until the workers reach their maximum number of passes
+let refining = false;
+
+// makes sure only a single frame is scheduled or rendered at a time
+let frameScheduled = false;
+let frameInFlight = false;
+
+function requestRender() {
+  if (frameScheduled || frameInFlight || workers.length === 0) return;
+  frameScheduled = true;
+  requestAnimationFrame(render);
+}
+
+// scene file rendered instead of the demo, workers cache the parsed scene and
+// are only sent a scene when its version changed
+let sceneJson = null;
+let sceneVersion = 0;
+
 let pastTimestamp = null; // only non-null when animation is running
 let animationTime = 0;
 
 function render(timestamp) {
+  frameScheduled = false;
   let start = new Date().getTime();
 
   if (animating) {
@@ -48,112 +109,174 @@ function render(timestamp) {
     document.querySelector("#resolution").value
   );
   let fov = (document.querySelector("#fov").value / 180) * Math.PI;
-  let supersampling = document.querySelector("#supersampling").checked;
-  let camera_x = document.querySelector("#camera-x").value;
-  let camera_y = document.querySelector("#camera-y").value;
-  let camera_z = document.querySelector("#camera-z").value;
-  let rotate_x = (document.querySelector("#rotate-x").value / 180) * Math.PI;
-  let rotate_y = (document.querySelector("#rotate-y").value / 180) * Math.PI;
-  let rotate_z = (document.querySelector("#rotate-z").value / 180) * Math.PI;
-
-  let width = Math.min(
-    Math.ceil(resolution_factor * document.body.scrollWidth),
-    2 * 1920
+  let samples_per_pixel = document.querySelector("#supersampling").checked
+    ? 5
+    : 1;
+  let denoise = document.querySelector("#denoise").checked;
+  let progressive = !animating && !renderingInput;
+  let filter = Number(document.querySelector("#filter").value);
+  let tone_mapper = Number(document.querySelector("#tone-mapper").value);
+  let exposure = Number(document.querySelector("#exposure").value);
+  let max_reflection_depth = Number(
+    document.querySelector("#reflections").value
   );
-  let height = Math.min(
-    Math.ceil(resolution_factor * document.body.scrollHeight),
-    2 * 1080
+  let max_refraction_depth = Number(
+    document.querySelector("#refractions").value
   );
+  let shutter = Number(document.querySelector("#shutter").value);
+  let camera_x = Number(document.querySelector("#camera-x").value);
+  let camera_y = Number(document.querySelector("#camera-y").value);
+  let camera_z = Number(document.querySelector("#camera-z").value);
+  let x_rot = (document.querySelector("#rotate-x").value / 180) * Math.PI;
+  let y_rot = (document.querySelector("#rotate-y").value / 180) * Math.PI;
+  let z_rot = (document.querySelector("#rotate-z").value / 180) * Math.PI;
+
+  let width = Math.ceil(resolution_factor * document.body.scrollWidth);
+  let height = Math.ceil(resolution_factor * document.body.scrollHeight);
 
   buffers[1 - visible_buf].canvasContext.clearRect(0, 0, width, height);
   buffers[1 - visible_buf].canvas.width = width;
   buffers[1 - visible_buf].canvas.height = height;
 
-  // parts of image that are rendered concurrently
-  let splits = [
-    {
-      start_x: 0,
-      end_x: Math.floor(width / 2),
-      start_y: 0,
-      end_y: Math.floor(height / 2),
-    },
-    {
-      start_x: 0,
-      end_x: Math.floor(width / 2),
-      start_y: Math.floor(height / 2),
-      end_y: height,
-    },
-    {
-      start_x: Math.floor(width / 2),
-      end_x: width,
-      start_y: 0,
-      end_y: Math.floor(height / 2),
-    },
-    {
-      start_x: Math.floor(width / 2),
-      end_x: width,
-      start_y: Math.floor(height / 2),
-      end_y: height,
-    },
-  ];
-
-  // render scene in workers
+  // the threaded worker renders the whole image at once
+  const tileSize =
+    workers.length === 1
+      ? Math.max(width, height, 1)
+      : denoise
+      ? DENOISE_TILE_SIZE
+      : TILE_SIZE;
+  const queue = [];
+  for (let start_y = 0; start_y < height; start_y += tileSize) {
+    for (let start_x = 0; start_x < width; start_x += tileSize) {
+      queue.push({
+        start_x,
+        end_x: Math.min(start_x + tileSize, width),
+        start_y,
+        end_y: Math.min(start_y + tileSize, height),
+      });
+    }
+  }
+  const tileCount = queue.length;
+  const tileKey = (tile) => `${tile.start_x},${tile.start_y}`;
+
+  const layout = `${width}x${height}x${workers.length}`;
+  if (!progressive || layout !== tileLayout) {
+    tileOwners.clear();
+    tilePasses.clear();
+    tileLayout = layout;
+  }
+
+  const request = {
+    width,
+    height,
+    fov,
+    samples_per_pixel,
+    denoise,
+    progressive,
+    filter,
+    tone_mapper,
+    exposure,
+    max_reflection_depth,
+    max_refraction_depth,
+    shutter,
+    camera_x,
+    camera_y,
+    camera_z,
+    x_rot,
+    y_rot,
+    z_rot,
+    t: animationTime / 1000,
+  };
+
   let finished = 0;
-  for (const [i, { start_x, end_x, start_y, end_y }] of splits.entries()) {
-    const worker = workers[i];
-    worker.postMessage({
-      width,
-      height,
-      start_x,
-      end_x,
-      start_y,
-      end_y,
-      fov,
-      supersampling,
-      camera_x,
-      camera_y,
-      camera_z,
-      rotate_x,
-      rotate_y,
-      rotate_z,
-      t: animationTime / 1000,
+  let error = null;
+  refining = false;
+  frameInFlight = true;
+
+  const frameFinished = () => {
+    frameInFlight = false;
+    if (error !== null) {
+      // the workers keep rendering their previous scene
+      showError(error);
+      requestRender();
+      return;
+    }
+
+    visible_buf = 1 - visible_buf;
+    buffers[visible_buf].canvas.style.visibility = "visible";
+    buffers[1 - visible_buf].canvas.style.visibility = "hidden";
+
+    let end = new Date().getTime();
+    document.querySelector("#frame-time").innerHTML = end - start;
+    document.querySelector("#passes").innerHTML = progressive
+      ? Math.min(...tilePasses.values())
+      : 1;
+    if (animating || renderingInput || !progressive || refining)
+      requestRender();
+  };
+
+  // hands the next tile the worker may render to it, until the queue is empty
+  const dispatch = (i) => {
+    const index = queue.findIndex((tile) => {
+      const owner = tileOwners.get(tileKey(tile));
+      return owner === undefined || owner === i;
     });
-    data = [];
+    if (index === -1) return;
+    const tile = queue.splice(index, 1)[0];
+    if (progressive) tileOwners.set(tileKey(tile), i);
+
+    const worker = workers[i];
     worker.onmessage = (msg) => {
-      const { imageDataArray } = msg.data;
-      const canvasImageData = buffers[
-        1 - visible_buf
-      ].canvasContext.createImageData(width, height);
-      canvasImageData.data.set(imageDataArray);
-      buffers[1 - visible_buf].canvasContext.putImageData(
-        canvasImageData,
-        0,
-        0,
-        start_x,
-        start_y,
-        end_x - start_x,
-        end_y - start_y
-      );
+      if (msg.data.error !== undefined) {
+        error = msg.data.error;
+      } else {
+        const { pixels, passes } = msg.data;
+        const key = tileKey(tile);
+        if (progressive) {
+          if (passes > (tilePasses.get(key) || 0)) refining = true;
+          tilePasses.set(key, passes);
+        }
+        // empty tiles cannot form an ImageData
+        if (pixels.length > 0) {
+          const tileImageData = new ImageData(
+            pixels,
+            tile.end_x - tile.start_x,
+            tile.end_y - tile.start_y
+          );
+          buffers[1 - visible_buf].canvasContext.putImageData(
+            tileImageData,
+            tile.start_x,
+            tile.start_y
+          );
+        }
+      }
 
       finished++;
-      if (finished === splits.length) {
-        visible_buf = 1 - visible_buf;
-        buffers[visible_buf].canvas.style.visibility = "visible";
-        buffers[1 - visible_buf].canvas.style.visibility = "hidden";
-
-        let end = new Date().getTime();
-        document.querySelector("#frame-time").innerHTML = end - start;
-        if (animating || renderingInput) requestAnimationFrame(render);
+      if (finished === tileCount) {
+        frameFinished();
+      } else {
+        dispatch(i);
       }
     };
+
+    const sceneChange =
+      workerSceneVersions[i] === sceneVersion ? {} : { scene: sceneJson };
+    workerSceneVersions[i] = sceneVersion;
+    worker.postMessage({ ...sceneChange, ...request, ...tile });
+  };
+
+  if (tileCount === 0) {
+    frameInFlight = false;
+    return;
   }
+  workers.forEach((_, i) => dispatch(i));
 }
 
-requestAnimationFrame(render); // initial render
+startWorkers().then(requestRender); // initial render
 
 const startInput = () => {
   renderingInput = true;
-  if (!animating) requestAnimationFrame(render);
+  if (!animating) requestRender();
 };
 
 const endInput = () => {
@@ -167,7 +290,7 @@ function playPause() {
   if (playPauseBtn.classList.contains("playing")) {
     playPauseBtn.innerText = "Pause";
     animating = true;
-    if (!renderingInput) requestAnimationFrame(render);
+    if (!renderingInput) requestRender();
   } else {
     playPauseBtn.innerText = "Play";
     animating = false;
@@ -177,6 +300,10 @@ function playPause() {
 
 document.querySelector("#resolution").addEventListener("mousedown", startInput);
 document.querySelector("#fov").addEventListener("mousedown", startInput);
+document.querySelector("#exposure").addEventListener("mousedown", startInput);
+document.querySelector("#reflections").addEventListener("mousedown", startInput);
+document.querySelector("#refractions").addEventListener("mousedown", startInput);
+document.querySelector("#shutter").addEventListener("mousedown", startInput);
 document.querySelector("#camera-x").addEventListener("mousedown", startInput);
 document.querySelector("#camera-y").addEventListener("mousedown", startInput);
 document.querySelector("#camera-z").addEventListener("mousedown", startInput);
@@ -186,6 +313,10 @@ document.querySelector("#rotate-z").addEventListener("mousedown", startInput);
 
 document.querySelector("#resolution").addEventListener("mouseup", endInput);
 document.querySelector("#fov").addEventListener("mouseup", endInput);
+document.querySelector("#exposure").addEventListener("mouseup", endInput);
+document.querySelector("#reflections").addEventListener("mouseup", endInput);
+document.querySelector("#refractions").addEventListener("mouseup", endInput);
+document.querySelector("#shutter").addEventListener("mouseup", endInput);
 document.querySelector("#supersampling").addEventListener("mouseup", endInput);
 document.querySelector("#camera-x").addEventListener("mouseup", endInput);
 document.querySelector("#camera-y").addEventListener("mouseup", endInput);
@@ -198,3 +329,63 @@ document.querySelector("#supersampling").addEventListener("change", () => {
   startInput();
   endInput();
 });
+document.querySelector("#denoise").addEventListener("change", () => {
+  startInput();
+  endInput();
+});
+document.querySelector("#filter").addEventListener("change", () => {
+  startInput();
+  endInput();
+});
+document.querySelector("#tone-mapper").addEventListener("change", () => {
+  startInput();
+  endInput();
+});
+
+// scenes
+function showError(message) {
+  const errorBox = document.querySelector("#error-box");
+  document.querySelector("#error").textContent = message;
+  errorBox.style.display = "block";
+}
+
+function hideError() {
+  document.querySelector("#error-box").style.display = "none";
+}
+
+function setScene(json) {
+  sceneJson = json;
+  sceneVersion++;
+  hideError();
+  startInput();
+  endInput();
+}
+
+document.querySelector("#error-box").addEventListener("click", hideError);
+
+document.querySelector("#scene").addEventListener("change", (event) => {
+  const preset = event.target.value;
+  if (preset === "") {
+    setScene(null);
+    return;
+  }
+  fetch(`scenes/${preset}.json`)
+    .then((response) => {
+      if (!response.ok) throw new Error(`cannot load preset ${preset}`);
+      return response.text();
+    })
+    .then(setScene)
+    .catch((error) => showError(error.message));
+});
+
+// scene files can be dropped anywhere onto the page
+document.body.addEventListener("dragover", (event) => event.preventDefault());
+document.body.addEventListener("drop", (event) => {
+  event.preventDefault();
+  const file = event.dataTransfer.files[0];
+  if (file === undefined) return;
+  file
+    .text()
+    .then(setScene)
+    .catch((error) => showError(`cannot read ${file.name}: ${error.message}`));
+});
@@ -1,79 +1,377 @@
-use raytracer::color::*;
+use raytracer::camera::*;
+use raytracer::demo::*;
+use raytracer::denoise::*;
+use raytracer::description::*;
+use raytracer::film::*;
+use raytracer::progressive::*;
+use raytracer::render::*;
 use raytracer::sampling::*;
+use raytracer::scene::*;
 use raytracer::structs::*;
+use raytracer::tonemap::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
 
-pub mod scene;
-use scene::*;
+#[cfg(feature = "threads")]
+pub mod threads;
 
-// setup memory to be read from JS
-const MAX_WIDTH: usize = 2 * 1920;
-const MAX_HEIGHT: usize = 2 * 1080;
-const OUTPUT_BUFFER_SIZE: usize = MAX_WIDTH * MAX_HEIGHT * 4;
-static mut OUTPUT_BUFFER: [u8; OUTPUT_BUFFER_SIZE] = [0; OUTPUT_BUFFER_SIZE];
+// number of passes after which progressive rendering stops refining
+const MAX_PASSES: usize = 64;
 
-// Function to return a pointer to our buffer in wasm memory
+thread_local! {
+    // accumulated images of the tiles rendered by this worker, by their
+    // region, while the view is unchanged
+    static PROGRESSIVE: RefCell<HashMap<Region, ProgressiveRenderer>> = RefCell::new(HashMap::new());
+
+    // scene loaded from a scene file, the demo scene is rendered if unset
+    static SCENE: RefCell<Option<SceneDescription>> = const { RefCell::new(None) };
+}
+
+// parses a JSON scene file (see raytracer/src/description.rs) and renders it
+// instead of the demo scene until another scene is loaded. On errors, the
+// previous scene is kept and a readable message is thrown.
+#[wasm_bindgen]
+pub fn load_scene(json: &str) -> Result<(), JsError> {
+    let description = SceneDescription::from_json(json).map_err(|e| JsError::new(&e))?;
+    SCENE.with(|scene| *scene.borrow_mut() = Some(description));
+    Ok(())
+}
+
+// switches back to the demo scene
+#[wasm_bindgen]
+pub fn clear_scene() {
+    SCENE.with(|scene| *scene.borrow_mut() = None);
+}
+
+// everything describing a call to generate_image, created in JS with the
+// image size and adjusted through its fields, all other fields start out with
+// the defaults below
 #[wasm_bindgen]
-pub fn get_output_buffer_pointer() -> *const u8 {
-    let pointer: *const u8;
-    unsafe {
-        pointer = OUTPUT_BUFFER.as_ptr();
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderRequest {
+    pub width: usize,
+    pub height: usize,
+    // region of the image to render
+    pub start_x: usize,
+    pub end_x: usize,
+    pub start_y: usize,
+    pub end_y: usize,
+    pub camera_x: Float,
+    pub camera_y: Float,
+    pub camera_z: Float,
+    // camera rotations and field of view in radians
+    pub x_rot: Float,
+    pub y_rot: Float,
+    pub z_rot: Float,
+    pub fov: Float,
+    pub samples_per_pixel: usize,
+    pub denoise: bool,
+    // add the samples to those of previous calls while the view is unchanged
+    pub progressive: bool,
+    pub filter: Filter,
+    pub tone_mapper: ToneMapper,
+    pub exposure: Float,
+    pub max_reflection_depth: u32,
+    pub max_refraction_depth: u32,
+    pub min_contribution: Float,
+    pub throughput_cutoff: Float,
+    // seconds the shutter is open around t, for motion blur
+    pub shutter: Float,
+    // animation time in seconds
+    pub t: Float,
+}
+
+#[wasm_bindgen]
+impl RenderRequest {
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: usize, height: usize) -> RenderRequest {
+        RenderRequest {
+            width,
+            height,
+            start_x: 0,
+            end_x: width,
+            start_y: 0,
+            end_y: height,
+            camera_x: 0.,
+            camera_y: 0.,
+            camera_z: 0.,
+            x_rot: 0.,
+            y_rot: 0.,
+            z_rot: 0.,
+            fov: DEFAULT_FOV,
+            samples_per_pixel: 1,
+            denoise: false,
+            progressive: false,
+            filter: Filter::Gaussian,
+            tone_mapper: DEFAULT_TONE_MAPPING.tone_mapper,
+            exposure: DEFAULT_TONE_MAPPING.exposure,
+            max_reflection_depth: DEFAULT_RENDER_SETTINGS.max_reflection_depth,
+            max_refraction_depth: DEFAULT_RENDER_SETTINGS.max_refraction_depth,
+            min_contribution: DEFAULT_RENDER_SETTINGS.min_contribution,
+            throughput_cutoff: DEFAULT_RENDER_SETTINGS.throughput_cutoff,
+            shutter: DEFAULT_RENDER_SETTINGS.shutter,
+            t: 0.,
+        }
     }
+}
 
-    return pointer;
+// 80 degrees
+const DEFAULT_FOV: Float = 1.396263;
+
+// result of generate_image, only covering the requested region
+#[wasm_bindgen]
+pub struct RenderedTile {
+    pub width: usize,
+    pub height: usize,
+    // number of passes in the image, always 1 unless rendering progressively
+    pub passes: usize,
+    pixels: Vec<u8>,
 }
 
 #[wasm_bindgen]
-pub fn generate_image(
-    width: usize,
-    height: usize,
-    start_x: usize,
-    end_x: usize,
-    start_y: usize,
-    end_y: usize,
-    camera_x: Float,
-    camera_y: Float,
-    camera_z: Float,
-    x_rot: Float,
-    y_rot: Float,
-    z_rot: Float,
-    fov: Float,
-    supersampling: bool,
-    t: Float,
-) {
-    let (scene, camera) = render_scene(
-        camera_x, camera_y, camera_z, x_rot, y_rot, z_rot, fov, width, height, t,
+impl RenderedTile {
+    // moves the RGBA pixels, row by row, into a Uint8ClampedArray, which can
+    // be used for ImageData directly. Leaves the tile empty.
+    pub fn take_pixels(&mut self) -> Clamped<Vec<u8>> {
+        Clamped(std::mem::take(&mut self.pixels))
+    }
+}
+
+// start_x, end_x, start_y and end_y of a tile
+type Region = (usize, usize, usize, usize);
+
+// renders the requested region of the image, usually a small tile. Tiles
+// rendered progressively keep accumulating as long as they are requested from
+// the same worker.
+#[wasm_bindgen]
+pub fn generate_image(request: &RenderRequest) -> RenderedTile {
+    let (scene, camera) = view(request);
+    PROGRESSIVE.with(|tiles| {
+        let mut tiles = tiles.borrow_mut();
+        if !request.progressive {
+            tiles.clear();
+        }
+        tiles.retain(|_, renderer| {
+            (renderer.film.width, renderer.film.height) == (request.width, request.height)
+        });
+
+        let region = (
+            request.start_x,
+            request.end_x,
+            request.start_y,
+            request.end_y,
+        );
+        let mut progressive = tiles.remove(&region);
+        let tile = render_region(request, &scene, &camera, &mut progressive);
+        if let Some(renderer) = progressive {
+            tiles.insert(region, renderer);
+        }
+        tile
+    })
+}
+
+// scene and camera seen by the request at its time, from the loaded scene
+// file if any
+fn view(request: &RenderRequest) -> (Scene, Camera) {
+    let description = SCENE
+        .with(|scene| scene.borrow().clone())
+        .unwrap_or_else(demo_scene);
+    let offset = Vec3 {
+        x: request.camera_x,
+        y: request.camera_y,
+        z: request.camera_z,
+    };
+    let camera = description.camera(
+        offset,
+        request.x_rot,
+        request.y_rot,
+        request.z_rot,
+        request.fov,
+        request.width,
+        request.height,
+        request.t,
+    );
+    (description.at(request.t), camera)
+}
+
+// renders the requested region of the view, accumulating into the given
+// progressive renderer if the request is progressive
+fn render_region(
+    request: &RenderRequest,
+    scene: &Scene,
+    camera: &Camera,
+    progressive: &mut Option<ProgressiveRenderer>,
+) -> RenderedTile {
+    if !request.denoise {
+        let (film, passes) = render_film_region(request, scene, camera, progressive);
+        return develop_tile(request, &film, passes);
+    }
+
+    // the region is rendered and denoised with an apron as wide as the
+    // footprint of the filter around it, so the border pixels are filtered
+    // exactly like in the full image and neighbouring tiles match up. The
+    // apron of the default settings is 62 pixels, which would render 8 times
+    // the pixels of a 64 pixel tile, so the page requests 256 pixel tiles
+    // when denoising, with 2.2 times the pixels.
+    let apron = DEFAULT_DENOISE_SETTINGS.footprint();
+    let padded = RenderRequest {
+        start_x: request.start_x.saturating_sub(apron),
+        end_x: (request.end_x + apron).min(request.width),
+        start_y: request.start_y.saturating_sub(apron),
+        end_y: (request.end_y + apron).min(request.height),
+        ..*request
+    };
+    let (mut film, passes) = render_film_region(&padded, scene, camera, progressive);
+    denoise(&mut film, &DEFAULT_DENOISE_SETTINGS);
+
+    let mut tile = Film::new(
+        request.width,
+        request.height,
+        request.start_x,
+        request.end_x,
+        request.start_y,
+        request.end_y,
+        request.filter,
+    );
+    tile.set_resolved(
+        (request.start_y..request.end_y)
+            .flat_map(|y| (request.start_x..request.end_x).map(move |x| (x, y)))
+            .map(|(x, y)| film.pixel(x, y))
+            .collect(),
     );
+    develop_tile(request, &tile, passes)
+}
 
-    let sample_grid: &[(Float, Float)] = if supersampling {
-        &SAMPLE_GRID_5
-    } else {
-        &SAMPLE_GRID_1
+// the film of the requested region and its number of passes, recording AOVs
+// but not yet denoised if the request asks for denoising
+fn render_film_region(
+    request: &RenderRequest,
+    scene: &Scene,
+    camera: &Camera,
+    progressive: &mut Option<ProgressiveRenderer>,
+) -> (Film, usize) {
+    let RenderRequest {
+        width,
+        height,
+        start_x,
+        end_x,
+        start_y,
+        end_y,
+        filter,
+        ..
+    } = *request;
+    let samples_per_pixel = request.samples_per_pixel.max(1);
+    let settings = RenderSettings {
+        max_reflection_depth: request.max_reflection_depth,
+        max_refraction_depth: request.max_refraction_depth,
+        min_contribution: request.min_contribution,
+        throughput_cutoff: request.throughput_cutoff,
+        shutter: request.shutter,
     };
+    let sampler = StratifiedSampler::new(samples_per_pixel);
 
-    let width_ = (width as Float) / 2.;
-    let height_ = (height as Float) / 2.;
-    for y in start_y..end_y {
-        for x in start_x..end_x {
-            let mut c: Color = COLOR_ZERO;
+    let new_film = || {
+        let mut film = Film::new(width, height, start_x, end_x, start_y, end_y, filter);
+        if request.denoise {
+            film.enable_aovs();
+        }
+        film
+    };
 
-            for (dx, dy) in sample_grid {
-                let sample_x = (dx + x as Float - width_) / width_;
-                let sample_y = (dy + y as Float - height_) / height_;
-                let ray = &camera.grid_ray(sample_x, sample_y);
+    if request.progressive {
+        let reusable = progressive.as_ref().is_some_and(|renderer| {
+            let film = &renderer.film;
+            renderer.samples_per_pass == samples_per_pixel
+                && (film.width, film.height) == (width, height)
+                && (film.start_x, film.end_x, film.start_y, film.end_y)
+                    == (start_x, end_x, start_y, end_y)
+                && film.filter == filter
+                && film.aovs.is_some() == request.denoise
+        });
+        if !reusable {
+            *progressive = Some(ProgressiveRenderer::new(new_film(), samples_per_pixel));
+        }
 
-                c += scene.shade(ray, 1);
-            }
-            c = c.scale(255. / (sample_grid.len() as Float));
-
-            let square_number: usize = y * width + x;
-            let square_rgba_index: usize = square_number * 4;
-            unsafe {
-                OUTPUT_BUFFER[square_rgba_index + 0] = c.r as u8; // Red
-                OUTPUT_BUFFER[square_rgba_index + 1] = c.g as u8; // Green
-                OUTPUT_BUFFER[square_rgba_index + 2] = c.b as u8; // Blue
-                OUTPUT_BUFFER[square_rgba_index + 3] = 255;
+        let renderer = progressive.as_mut().unwrap();
+        if renderer.passes < MAX_PASSES {
+            renderer.render_pass(scene, camera, &sampler, &settings);
+        }
+        (renderer.film.clone(), renderer.passes)
+    } else {
+        *progressive = None;
+        let mut film = new_film();
+        render_film(
+            scene,
+            camera,
+            &sampler,
+            samples_per_pixel,
+            &settings,
+            &mut film,
+        );
+        (film, 1)
+    }
+}
+
+// tone maps the film of the requested region
+fn develop_tile(request: &RenderRequest, film: &Film, passes: usize) -> RenderedTile {
+    let pixels = film.develop(&ToneMapping {
+        tone_mapper: request.tone_mapper,
+        exposure: request.exposure,
+    });
+    RenderedTile {
+        width: film.end_x - film.start_x,
+        height: film.end_y - film.start_y,
+        passes,
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 160;
+    const HEIGHT: usize = 96;
+    const TILE: usize = 64;
+
+    fn request() -> RenderRequest {
+        RenderRequest {
+            denoise: true,
+            ..RenderRequest::new(WIDTH, HEIGHT)
+        }
+    }
+
+    // the image put together from tiles of the given request, the last
+    // tiles of every row and column are cut off at the border
+    fn tiled(request: &RenderRequest) -> Vec<u8> {
+        let mut pixels = vec![0; WIDTH * HEIGHT * 4];
+        for start_y in (0..HEIGHT).step_by(TILE) {
+            for start_x in (0..WIDTH).step_by(TILE) {
+                let (end_x, end_y) = ((start_x + TILE).min(WIDTH), (start_y + TILE).min(HEIGHT));
+                let mut tile = generate_image(&RenderRequest {
+                    start_x,
+                    end_x,
+                    start_y,
+                    end_y,
+                    ..*request
+                });
+                let tile_pixels = tile.take_pixels().0;
+                let row = (end_x - start_x) * 4;
+                for (y, tile_row) in (start_y..end_y).zip(tile_pixels.chunks(row)) {
+                    let start = (y * WIDTH + start_x) * 4;
+                    pixels[start..start + row].copy_from_slice(tile_row);
+                }
             }
         }
+        pixels
+    }
+
+    #[test]
+    fn denoised_tiles_match_the_denoised_image() {
+        let request = request();
+        let full = generate_image(&request).take_pixels().0;
+        assert_eq!(tiled(&request), full);
     }
 }
@@ -0,0 +1,123 @@
+use rayon::prelude::*;
+use raytracer::progressive::*;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+
+use super::*;
+
+// exported as initThreadPool, has to be awaited before rendering
+pub use wasm_bindgen_rayon::init_thread_pool;
+
+// rows of the image rendered as one job of the thread pool
+const BAND_HEIGHT: usize = 8;
+
+thread_local! {
+    // progressive state of every band, owned by the thread calling generate_frame
+    static BANDS: RefCell<Vec<Option<ProgressiveRenderer>>> = const { RefCell::new(Vec::new()) };
+}
+
+// renders the requested region like generate_image, but on all threads of
+// the pool. The scene is built once and shared by all threads, which render
+// bands of rows into films of their own, since samples near the border of a
+// band are splatted onto the neighbouring band as well. The band films are
+// then copied into one film for the region on the calling thread, which is
+// denoised as a whole, so the filter sees across the borders of the bands.
+#[wasm_bindgen]
+pub fn generate_frame(request: &RenderRequest) -> RenderedTile {
+    let (scene, camera) = view(request);
+    let (start_y, end_y) = (request.start_y, request.end_y);
+
+    BANDS.with(|bands| {
+        let mut bands = bands.borrow_mut();
+        let band_count = (end_y - start_y).div_ceil(BAND_HEIGHT);
+        bands.resize_with(band_count, || None);
+
+        let films: Vec<(Film, usize)> = bands
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, progressive)| {
+                let band_start = start_y + i * BAND_HEIGHT;
+                let band = RenderRequest {
+                    start_y: band_start,
+                    end_y: (band_start + BAND_HEIGHT).min(end_y),
+                    ..*request
+                };
+                render_film_region(&band, &scene, &camera, progressive)
+            })
+            .collect();
+
+        let mut frame = Film::new(
+            request.width,
+            request.height,
+            request.start_x,
+            request.end_x,
+            start_y,
+            end_y,
+            request.filter,
+        );
+        if request.denoise {
+            frame.enable_aovs();
+        }
+        for (film, _) in &films {
+            frame.insert(film);
+        }
+        if request.denoise {
+            denoise(&mut frame, &DEFAULT_DENOISE_SETTINGS);
+        }
+        let passes = films.iter().map(|&(_, passes)| passes).min().unwrap_or(1);
+        develop_tile(request, &frame, passes)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // rows not divisible into whole bands
+    const WIDTH: usize = 48;
+    const HEIGHT: usize = 36;
+
+    // generate_image renders the region as a single film on the calling thread
+    fn assert_matches_single_film(request: &RenderRequest) {
+        let mut frame = generate_frame(request);
+        let mut image = generate_image(request);
+        assert_eq!(frame.passes, image.passes);
+        assert_eq!(frame.take_pixels().0, image.take_pixels().0);
+    }
+
+    #[test]
+    fn bands_assemble_into_the_image() {
+        let request = RenderRequest {
+            samples_per_pixel: 2,
+            ..RenderRequest::new(WIDTH, HEIGHT)
+        };
+        assert_matches_single_film(&request);
+        // a region starting within a band
+        assert_matches_single_film(&RenderRequest {
+            start_x: 5,
+            end_x: 40,
+            start_y: 3,
+            end_y: 30,
+            ..request
+        });
+    }
+
+    #[test]
+    fn bands_are_denoised_as_one_image() {
+        assert_matches_single_film(&RenderRequest {
+            denoise: true,
+            ..RenderRequest::new(WIDTH, HEIGHT)
+        });
+    }
+
+    #[test]
+    fn bands_accumulate_progressively() {
+        let request = RenderRequest {
+            progressive: true,
+            ..RenderRequest::new(WIDTH, HEIGHT)
+        };
+        for _ in 0..3 {
+            assert_matches_single_film(&request);
+        }
+    }
+}
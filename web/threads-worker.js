@@ -0,0 +1,43 @@
+// module worker rendering with the shared memory thread pool of the threaded
+// build in pkg-threads, see README.md. Handles the same messages as worker.js,
+// but renders its region on all cores.
+import init, {
+  initThreadPool,
+  RenderRequest,
+  generate_frame,
+  load_scene,
+  clear_scene,
+} from "./pkg-threads/web.js";
+
+// the page falls back to worker.js unless this reports being ready
+init()
+  .then(() => initThreadPool(navigator.hardwareConcurrency))
+  .then(() => self.postMessage({ ready: true }))
+  .catch((error) => self.postMessage({ ready: false, error: error.message }));
+
+self.onmessage = (msg) => {
+  if (msg.data.scene !== undefined) {
+    try {
+      if (msg.data.scene === null) {
+        clear_scene();
+      } else {
+        load_scene(msg.data.scene);
+      }
+    } catch (error) {
+      self.postMessage({ error: error.message });
+      return;
+    }
+  }
+
+  const { width, height } = msg.data;
+  const request = new RenderRequest(width, height);
+  Object.assign(request, msg.data);
+
+  const tile = generate_frame(request);
+  request.free();
+  const { passes } = tile;
+  const pixels = tile.take_pixels();
+  tile.free();
+
+  self.postMessage({ pixels, passes }, [pixels.buffer]);
+};
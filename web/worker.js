@@ -7,46 +7,32 @@ self.onmessage = async (msg) => {
     rustWasm = await wasm_bindgen("./pkg/web_bg.wasm");
   }
 
-  const {
-    width,
-    height,
-    start_x,
-    end_x,
-    start_y,
-    end_y,
-    fov,
-    supersampling,
-    camera_x,
-    camera_y,
-    camera_z,
-    rotate_x,
-    rotate_y,
-    rotate_z,
-    t,
-  } = msg.data;
+  // a scene is only sent when it changed, null switches back to the demo
+  if (msg.data.scene !== undefined) {
+    try {
+      if (msg.data.scene === null) {
+        wasm_bindgen.clear_scene();
+      } else {
+        wasm_bindgen.load_scene(msg.data.scene);
+      }
+    } catch (error) {
+      self.postMessage({ error: error.message });
+      return;
+    }
+  }
+
+  // every field of the message with the name of a RenderRequest field
+  // overrides its default, see web/src/lib.rs for all of them
+  const { width, height } = msg.data;
+  const request = new wasm_bindgen.RenderRequest(width, height);
+  Object.assign(request, msg.data);
+
+  const tile = wasm_bindgen.generate_image(request);
+  request.free();
+  const { passes } = tile;
+  const pixels = tile.take_pixels();
+  tile.free();
 
-  rustWasm.generate_image(
-    width,
-    height,
-    start_x,
-    end_x,
-    start_y,
-    end_y,
-    camera_x,
-    camera_y,
-    camera_z,
-    rotate_x,
-    rotate_y,
-    rotate_z,
-    fov,
-    supersampling,
-    t
-  );
-  const wasmByteMemoryArray = new Uint8Array(rustWasm.memory.buffer);
-  const outputPointer = rustWasm.get_output_buffer_pointer();
-  const imageDataArray = wasmByteMemoryArray.slice(
-    outputPointer,
-    outputPointer + width * height * 4
-  );
-  self.postMessage({ imageDataArray });
+  // transfer the pixels instead of copying them to the main thread
+  self.postMessage({ pixels, passes }, [pixels.buffer]);
 };